@@ -1,7 +1,18 @@
 use std::{path::PathBuf, process::exit};
 
 use anyhow::{anyhow, Result};
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::{
+    decoder::Alphabet,
+    fading::Fading,
+    keyer::Envelope,
+    noise::NoiseKind,
+    oscillator::{Waveform, MAX_HARMONICS},
+    raw::RawFormat,
+    sink::FileFormat,
+    timing::{cpm_to_wpm, Level, Ramp, RampUnit, Standard, MAX_WPM, MIN_WPM},
+};
 
 #[derive(Clone, Debug, PartialEq, ValueEnum)]
 pub enum DumpType {
@@ -10,11 +21,46 @@ pub enum DumpType {
 }
 
 #[derive(Clone, Debug, Parser, Default)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 pub struct Args {
-    /// Morse code speed in `wpm` units
-    #[arg(short, long, default_value = "25")]
-    pub wpm: u8,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Morse code speed in `wpm` units (character speed)
+    #[arg(short, long, visible_alias = "char-wpm", default_value = "25")]
+    pub wpm: f64,
+
+    /// Morse code speed in characters per minute, instead of `--wpm` (5 CPM = 1 WPM)
+    #[arg(long, conflicts_with_all = ["wpm", "level"])]
+    pub cpm: Option<f64>,
+
+    /// Speed of a JARL skill level (e.g. `初段`, `3級`, or `shodan`, `3kyu`), instead of `--wpm`
+    #[arg(long, value_enum, conflicts_with = "wpm")]
+    pub level: Option<Level>,
+
+    /// Gradually change the speed from `--wpm` to this speed over `--ramp-over`
+    #[arg(long, value_name = "WPM", requires = "ramp_over")]
+    pub ramp_to: Option<f64>,
+
+    /// Amount of the text over which the speed is ramped, in units of `--ramp-by`
+    #[arg(long, value_name = "N", requires = "ramp_to")]
+    pub ramp_over: Option<f64>,
+
+    /// Unit of `--ramp-over` (lines, seconds or characters)
+    #[arg(long, value_enum, default_value = "line")]
+    pub ramp_by: RampUnit,
+
+    /// Speed of lines with wabun kana in percent of the speed
+    #[arg(long, value_name = "PERCENT", default_value = "75")]
+    pub wabun_ratio: f64,
+
+    /// Overall speed in `wpm` units, stretching the spaces by the ARRL formula [default: same as wpm]
+    #[arg(long, value_name = "WPM")]
+    pub effective_wpm: Option<f64>,
+
+    /// Reference word of the speed
+    #[arg(long, value_enum, default_value = "paris")]
+    pub standard: Standard,
 
     /// Morse code frequency
     #[arg(short, long, default_value = "600.0")]
@@ -32,6 +78,105 @@ pub struct Args {
     #[arg(long, default_value = "1.0")]
     pub farnsworth_timing: f32,
 
+    /// Wordsworth timing (multiplier of the space between words only)
+    #[arg(long, default_value = "1.0")]
+    pub wordsworth: f32,
+
+    /// Length of a dah in dits
+    #[arg(long, value_name = "RATIO", default_value = "3.0")]
+    pub dah_ratio: f64,
+
+    /// Key-down share of a dit and the following space in percent (50 is even)
+    #[arg(long, value_name = "PERCENT", default_value = "50")]
+    pub weight: f64,
+
+    /// Random timing jitter of each element and space in percent (standard deviation)
+    #[arg(long, value_name = "PERCENT", default_value = "0")]
+    pub jitter: f64,
+
+    /// Make dahs heavier by PERCENT of a dit like a bug key, with machine-timed dits
+    #[arg(long, value_name = "PERCENT", default_value = "0")]
+    pub bug: f64,
+
+    /// Rise and fall time of each element (e.g. `5ms`)
+    #[arg(long, value_name = "MS", default_value = "5ms", value_parser = parse_millis)]
+    pub rise_time: f32,
+
+    /// Shape of the rise and fall
+    #[arg(long, value_enum, default_value = "cosine")]
+    pub envelope: Envelope,
+
+    /// Waveform of the tone (`-bl` variants are band-limited)
+    #[arg(long, value_enum, default_value = "sine")]
+    pub waveform: Waveform,
+
+    /// Relative amplitudes of the 2nd, 3rd, ... harmonics added to the tone (e.g. `0.3,0.1`)
+    #[arg(long, value_name = "LIST", value_delimiter = ',')]
+    pub harmonics: Vec<f32>,
+
+    /// Frequency shift in Hz just after each key-down, settling within a few ms
+    #[arg(
+        long,
+        value_name = "HZ",
+        default_value = "0",
+        allow_negative_numbers = true
+    )]
+    pub chirp: f32,
+
+    /// Frequency drift in Hz over each line
+    #[arg(
+        long,
+        value_name = "HZ",
+        default_value = "0",
+        allow_negative_numbers = true
+    )]
+    pub drift: f32,
+
+    /// Depth of the AC hum modulation on the tone (0.0 .. 1.0)
+    #[arg(long, value_name = "DEPTH", default_value = "0")]
+    pub hum: f32,
+
+    /// Frequency of the AC hum in Hz
+    #[arg(long, value_name = "HZ", default_value = "100")]
+    pub hum_frequency: f32,
+
+    /// Kind of the background noise mixed under the signal (see `--snr`)
+    #[arg(long, value_enum, default_value = "white")]
+    pub noise: NoiseKind,
+
+    /// Signal to noise ratio of the background noise in dB [default: no noise]
+    #[arg(long, value_name = "DB", allow_negative_numbers = true)]
+    pub snr: Option<f32>,
+
+    /// QSB fading of the signal
+    #[arg(long, value_enum, default_value = "none")]
+    pub qsb: Fading,
+
+    /// How deep the signal fades out (0.0 .. 1.0)
+    #[arg(long, value_name = "DEPTH", default_value = "0.5")]
+    pub qsb_depth: f32,
+
+    /// Period of the fading in seconds
+    #[arg(long, value_name = "SECONDS", default_value = "8.0")]
+    pub qsb_period: f32,
+
+    /// Bandwidth of the receiver CW filter in Hz, applied after mixing [default: no filter]
+    #[arg(long, value_name = "HZ")]
+    pub filter: Option<f32>,
+
+    /// Offset of the filter center from `--frequency` in Hz (BFO offset)
+    #[arg(
+        long,
+        value_name = "HZ",
+        default_value = "0",
+        allow_negative_numbers = true
+    )]
+    pub bfo_offset: f32,
+
+    /// Seed of the random noise, fading and jitter, for reproducible drills [default: random]
+    #[arg(long)]
+    pub seed: Option<u64>,
+
     /// Dump message line by per char or per line
     #[arg(short, long)]
     pub dump: Option<DumpType>,
@@ -57,6 +202,142 @@ pub struct Args {
     /// Read message from file
     #[arg(short, long, value_name = "FILE", group("text"))]
     pub input: Option<PathBuf>,
+
+    /// Write the sound to a file (`.wav`, `.flac` or `.ogg`) instead of playing it
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// Title tag of the output file [default: input file name]
+    #[arg(long, requires = "output")]
+    pub title: Option<String>,
+
+    /// Write raw little-endian mono PCM to standard output instead of playing it
+    #[arg(long, value_name = "FORMAT", conflicts_with_all = ["output", "dump", "verbose"])]
+    pub raw: Option<RawFormat>,
+
+    /// Audio host to play on (see `--list-devices`)
+    #[arg(long, conflicts_with_all = ["output", "raw"])]
+    pub host: Option<String>,
+
+    /// Output device to play on (see `--list-devices`)
+    #[arg(long, conflicts_with_all = ["output", "raw"])]
+    pub device: Option<String>,
+
+    /// List audio hosts, output devices and their supported configs
+    #[arg(long, exclusive = true)]
+    pub list_devices: bool,
+
+    /// Sample rate of the output file or raw PCM
+    #[arg(long, default_value = "44100")]
+    pub sample_rate: u32,
+}
+
+// 発音以外の機能
+// (doc コメントにすると、clap がコマンド全体の説明として使ってしまう)
+#[derive(Clone, Debug, Subcommand)]
+pub enum Command {
+    /// Decode dot/dash notation (e.g. `-.-. --.-`), a WAV recording or live PCM back to text
+    Decode(DecodeArgs),
+    /// Find every CW signal in a WAV recording and decode each one with timestamps
+    Skim(SkimArgs),
+    /// Print the dot/dash notation of the message without playing it
+    Encode(EncodeArgs),
+}
+
+/// `decode` の引数
+#[derive(Clone, Debug, clap::Args)]
+pub struct DecodeArgs {
+    /// The dot/dash notation to decode [default: read from standard input]
+    #[arg(name = "CODE", allow_hyphen_values = true)]
+    pub code: Option<String>,
+
+    /// Decode the CW tone in a WAV recording instead of dot/dash notation
+    #[arg(long, value_name = "FILE", conflicts_with = "CODE")]
+    pub wav: Option<PathBuf>,
+
+    /// Decode the CW tone in raw PCM from standard input as it arrives (e.g. from `arecord`)
+    #[arg(long, conflicts_with_all = ["CODE", "wav"])]
+    pub stdin: bool,
+
+    /// Sample format of the raw PCM from standard input (little-endian mono)
+    #[arg(long, value_enum, default_value = "s16")]
+    pub format: RawFormat,
+
+    /// Sample rate of the raw PCM from standard input
+    #[arg(long, default_value = "44100")]
+    pub sample_rate: u32,
+
+    /// Frequency of the CW tone in the recording [default: the strongest tone]
+    #[arg(long, value_name = "HZ")]
+    pub tone: Option<f32>,
+
+    /// Alphabet to decode into (some codes are shared between latin and wabun)
+    #[arg(long, value_enum, default_value = "latin")]
+    pub alphabet: Alphabet,
+
+    /// Separator between letters
+    #[arg(long, value_name = "SEP", default_value = " ")]
+    pub letter_separator: String,
+
+    /// Separator between words
+    #[arg(long, value_name = "SEP", default_value = "/")]
+    pub word_separator: String,
+}
+
+/// `skim` の引数
+#[derive(Clone, Debug, clap::Args)]
+pub struct SkimArgs {
+    /// The WAV recording to skim
+    #[arg(name = "FILE")]
+    pub wav: PathBuf,
+
+    /// Lower edge of the passband to search in Hz
+    #[arg(long, value_name = "HZ", default_value = "200")]
+    pub low: f32,
+
+    /// Upper edge of the passband to search in Hz
+    #[arg(long, value_name = "HZ", default_value = "2000")]
+    pub high: f32,
+
+    /// Alphabet to decode into (some codes are shared between latin and wabun)
+    #[arg(long, value_enum, default_value = "latin")]
+    pub alphabet: Alphabet,
+}
+
+/// `encode` の引数
+#[derive(Clone, Debug, clap::Args)]
+pub struct EncodeArgs {
+    /// The message to encode [default: read from standard input]
+    #[arg(name = "TEXT")]
+    pub text: Option<String>,
+
+    /// Read message from file
+    #[arg(short, long, value_name = "FILE", conflicts_with = "TEXT")]
+    pub input: Option<PathBuf>,
+
+    /// Symbol for a dot (e.g. `·`)
+    #[arg(long, value_name = "SYM", default_value = ".")]
+    pub dot: String,
+
+    /// Symbol for a dash (e.g. `−`)
+    #[arg(long, value_name = "SYM", default_value = "-")]
+    pub dash: String,
+
+    /// Spell the code as it is spoken (e.g. `dah-di-dah-dit`) instead of dots and dashes
+    #[arg(long, conflicts_with_all = ["dot", "dash"])]
+    pub phonetic: bool,
+
+    /// Separator between letters
+    #[arg(long, value_name = "SEP", default_value = " ")]
+    pub letter_separator: String,
+
+    /// Separator between words
+    #[arg(long, value_name = "SEP", default_value = " / ")]
+    pub word_separator: String,
+
+    /// Print each line as a JSON object with the timing of every letter
+    #[arg(long)]
+    pub json: bool,
 }
 
 /// コマンドライン引数を解析し構造体に取り込む
@@ -78,11 +359,74 @@ pub fn get_args() -> Result<Args> {
     return Ok(opt);
 }
 
+impl Args {
+    /// `--ramp-to` で指定された、速度の変え方
+    pub fn ramp(&self) -> Ramp {
+        return match (self.ramp_to, self.ramp_over) {
+            (Some(end), Some(over)) => Ramp::new(self.char_wpm(), end, over, self.ramp_by),
+            _ => Ramp::default(),
+        };
+    }
+
+    /// `--cpm` や `--level` を考慮した、文字の速度[wpm]
+    pub fn char_wpm(&self) -> f64 {
+        if let Some(level) = self.level {
+            return cpm_to_wpm(level.cpm());
+        }
+        if let Some(cpm) = self.cpm {
+            return cpm_to_wpm(cpm);
+        }
+        return self.wpm;
+    }
+
+    /// ファイルや標準出力へ書き出すときのサンプリング周波数。デバイスで発音するなら `None`
+    pub fn render_sample_rate(&self) -> Option<u32> {
+        if self.output.is_some() || self.raw.is_some() {
+            return Some(self.sample_rate);
+        }
+        return None;
+    }
+}
+
+/// ミリ秒の指定を解釈する。単位 `ms` は省略可
+pub fn parse_millis(s: &str) -> Result<f32, String> {
+    let value = s.trim().trim_end_matches("ms");
+
+    return value
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid milliseconds: {}", s));
+}
+
 pub fn check_range(opt: &Args) -> Result<()> {
-    if opt.wpm < 3 || 60 < opt.wpm {
+    if !(MIN_WPM..=MAX_WPM).contains(&opt.wpm) {
         return Err(anyhow!("error: wpm out is of range ( 3 .. 60 )"));
     }
 
+    if let Some(cpm) = opt.cpm {
+        if !(15.0..=300.0).contains(&cpm) {
+            return Err(anyhow!("error: cpm is out of range ( 15 .. 300 )"));
+        }
+    }
+
+    if let Some(ramp_to) = opt.ramp_to {
+        if !(MIN_WPM..=MAX_WPM).contains(&ramp_to) {
+            return Err(anyhow!("error: ramp to is out of range ( 3 .. 60 )"));
+        }
+    }
+
+    if let Some(ramp_over) = opt.ramp_over {
+        if ramp_over <= 0.0 {
+            return Err(anyhow!("error: ramp over must be positive"));
+        }
+    }
+
+    if opt.wabun_ratio < 50.0 || 100.0 < opt.wabun_ratio {
+        return Err(anyhow!(
+            "error: wabun ratio is out of range ( 50 .. 100 % )"
+        ));
+    }
+
     if opt.frequency < 400.0 || 1200.0 < opt.frequency {
         return Err(anyhow!(
             "error: frequency is out of range ( 400.0 .. 1200.0 )"
@@ -97,12 +441,145 @@ pub fn check_range(opt: &Args) -> Result<()> {
         return Err(anyhow!("error: power is out of range ( 1.0 .. 5.0 )"));
     }
 
+    if let Some(effective_wpm) = opt.effective_wpm {
+        if effective_wpm < 3.0 || opt.char_wpm() < effective_wpm {
+            return Err(anyhow!("error: effective wpm is out of range ( 3 .. wpm )"));
+        }
+    }
+
     if opt.farnsworth_timing < 1.0 || 5.0 < opt.farnsworth_timing {
         return Err(anyhow!(
             "error: farnsworth timing is out of range ( 1.0 .. 5.0 )"
         ));
     }
 
+    if opt.wordsworth < 1.0 || 5.0 < opt.wordsworth {
+        return Err(anyhow!("error: wordsworth is out of range ( 1.0 .. 5.0 )"));
+    }
+
+    if opt.dah_ratio < 2.0 || 5.0 < opt.dah_ratio {
+        return Err(anyhow!("error: dah ratio is out of range ( 2.0 .. 5.0 )"));
+    }
+
+    if opt.weight < 25.0 || 75.0 < opt.weight {
+        return Err(anyhow!("error: weight is out of range ( 25 .. 75 % )"));
+    }
+
+    if opt.jitter < 0.0 || 30.0 < opt.jitter {
+        return Err(anyhow!("error: jitter is out of range ( 0 .. 30 % )"));
+    }
+
+    if opt.bug < 0.0 || 100.0 < opt.bug {
+        return Err(anyhow!("error: bug is out of range ( 0 .. 100 % )"));
+    }
+
+    if opt.rise_time < 0.0 || 50.0 < opt.rise_time {
+        return Err(anyhow!("error: rise time is out of range ( 0 .. 50 ms )"));
+    }
+
+    if MAX_HARMONICS < opt.harmonics.len() {
+        return Err(anyhow!(
+            "error: too many harmonics ( up to {} )",
+            MAX_HARMONICS
+        ));
+    }
+
+    if opt.harmonics.iter().any(|a| *a < 0.0 || 1.0 < *a) {
+        return Err(anyhow!("error: harmonics is out of range ( 0.0 .. 1.0 )"));
+    }
+
+    if opt.chirp < -200.0 || 200.0 < opt.chirp {
+        return Err(anyhow!("error: chirp is out of range ( -200 .. 200 Hz )"));
+    }
+
+    if opt.drift < -300.0 || 300.0 < opt.drift {
+        return Err(anyhow!("error: drift is out of range ( -300 .. 300 Hz )"));
+    }
+
+    if opt.hum < 0.0 || 1.0 < opt.hum {
+        return Err(anyhow!("error: hum is out of range ( 0.0 .. 1.0 )"));
+    }
+
+    if opt.hum_frequency < 40.0 || 400.0 < opt.hum_frequency {
+        return Err(anyhow!(
+            "error: hum frequency is out of range ( 40 .. 400 Hz )"
+        ));
+    }
+
+    if let Some(snr) = opt.snr {
+        if !(-20.0..=60.0).contains(&snr) {
+            return Err(anyhow!("error: snr is out of range ( -20 .. 60 dB )"));
+        }
+    }
+
+    if opt.qsb_depth < 0.0 || 1.0 < opt.qsb_depth {
+        return Err(anyhow!("error: qsb depth is out of range ( 0.0 .. 1.0 )"));
+    }
+
+    if opt.qsb_period < 0.5 || 120.0 < opt.qsb_period {
+        return Err(anyhow!(
+            "error: qsb period is out of range ( 0.5 .. 120.0 s )"
+        ));
+    }
+
+    if let Some(Command::Decode(ref decode)) = opt.command {
+        if decode.letter_separator.is_empty() || decode.word_separator.is_empty() {
+            return Err(anyhow!("error: separator must not be empty."));
+        }
+        if decode.letter_separator == decode.word_separator {
+            return Err(anyhow!(
+                "error: letter and word separators must be different."
+            ));
+        }
+        if let Some(tone) = decode.tone {
+            // `subcommand_negates_reqs` があると `requires` が効かないので、ここで検査する
+            if decode.wav.is_none() && !decode.stdin {
+                return Err(anyhow!("error: `--tone` needs `--wav` or `--stdin`."));
+            }
+            if !(100.0..=3000.0).contains(&tone) {
+                return Err(anyhow!("error: tone is out of range ( 100 .. 3000 Hz )"));
+            }
+        }
+        if let Some(ref path) = decode.wav {
+            if !path.try_exists().unwrap_or(false) {
+                return Err(anyhow!("error: file does not exist."));
+            }
+        }
+        if decode.stdin && (decode.sample_rate < 8000 || 192000 < decode.sample_rate) {
+            return Err(anyhow!(
+                "error: sample rate is out of range ( 8000 .. 192000 )"
+            ));
+        }
+    }
+
+    if let Some(Command::Skim(ref skim)) = opt.command {
+        if !(100.0..=3000.0).contains(&skim.low) || !(100.0..=3000.0).contains(&skim.high) {
+            return Err(anyhow!(
+                "error: passband is out of range ( 100 .. 3000 Hz )"
+            ));
+        }
+        if skim.high <= skim.low {
+            return Err(anyhow!("error: `--high` must be higher than `--low`."));
+        }
+        if !skim.wav.try_exists().unwrap_or(false) {
+            return Err(anyhow!("error: file does not exist."));
+        }
+    }
+
+    if let Some(Command::Encode(ref encode)) = opt.command {
+        if encode.dot.is_empty() || encode.dash.is_empty() {
+            return Err(anyhow!("error: symbol must not be empty."));
+        }
+        if encode.dot == encode.dash {
+            return Err(anyhow!("error: dot and dash symbols must be different."));
+        }
+        if let Some(path) = &encode.input {
+            if !path.try_exists().unwrap_or(false) {
+                return Err(anyhow!("error: file does not exist."));
+            }
+        }
+    }
+
     if let Some(path) = &opt.input {
         if let Ok(is_exist) = path.try_exists() {
             if !is_exist {
@@ -113,5 +590,58 @@ pub fn check_range(opt: &Args) -> Result<()> {
         }
     }
 
+    if let Some(path) = &opt.output {
+        let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase());
+        if ext.as_deref() == Some("opus") {
+            return Err(anyhow!(
+                "error: Ogg Opus is not supported; use `.ogg` (Ogg Vorbis) instead."
+            ));
+        }
+        if FileFormat::from_path(path).is_none() {
+            return Err(anyhow!(
+                "error: output file must be `.wav`, `.flac` or `.ogg`."
+            ));
+        }
+    }
+
+    // サンプリング周波数はファイルや標準出力へ書き出す場合のみ使用
+    let sample_rate = opt.render_sample_rate();
+    if sample_rate.is_some_and(|r| !(8000..=192000).contains(&r)) {
+        return Err(anyhow!(
+            "error: sample rate is out of range ( 8000 .. 192000 )"
+        ));
+    }
+
+    check_filter(opt.filter, opt.frequency + opt.bfo_offset, sample_rate)?;
+
+    return Ok(());
+}
+
+/// 受信機の CW フィルタの範囲チェック
+///
+/// 書き出すときは、通過域の上端がサンプリング周波数の 0.45 倍を下回ること。
+pub fn check_filter(filter: Option<f32>, center: f32, sample_rate: Option<u32>) -> Result<()> {
+    if let Some(filter) = filter {
+        if !(50.0..=3000.0).contains(&filter) {
+            return Err(anyhow!("error: filter is out of range ( 50 .. 3000 Hz )"));
+        }
+    }
+
+    if !(100.0..=3000.0).contains(&center) {
+        return Err(anyhow!(
+            "error: filter center (frequency + bfo offset) is out of range ( 100 .. 3000 Hz )"
+        ));
+    }
+
+    if let (Some(filter), Some(sample_rate)) = (filter, sample_rate) {
+        let limit = 0.45 * sample_rate as f32;
+        if limit <= center + filter / 2.0 {
+            return Err(anyhow!(
+                "error: filter passband (center + filter / 2) must be below {} Hz at this sample rate",
+                limit
+            ));
+        }
+    }
+
     return Ok(());
 }
@@ -0,0 +1,438 @@
+use std::collections::{HashMap, VecDeque};
+
+use clap::ValueEnum;
+
+use crate::translation_table::translation_entries;
+
+/// 復号する文字の種類
+///
+/// 欧文と和文では同じ符号を別の文字に使う(`.-` は A とイ)ので、どちらかを選ぶ。
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Alphabet {
+    /// 欧文(英数字と記号)
+    #[default]
+    Latin,
+    /// 和文(かなと記号)
+    Wabun,
+}
+
+impl Alphabet {
+    /// 文字がこの種類に属するか
+    ///
+    /// 和文の文字はすべて U+3000 以降(かな・全角記号)にある。
+    fn contains(&self, ch: char) -> bool {
+        return match self {
+            Alphabet::Latin => ch < '\u{3000}',
+            Alphabet::Wabun => '\u{3000}' <= ch,
+        };
+    }
+}
+
+/// 復号できない符号の代わりに出力する文字
+pub const UNKNOWN: char = '\u{FFFD}';
+
+/// 復号の結果
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Decoded {
+    /// 復号した文字列
+    pub text: String,
+    /// 複数の文字に当たる符号と、その候補(先頭の文字を採用)
+    pub ambiguous: Vec<(String, Vec<char>)>,
+    /// どの文字にも当たらない符号
+    pub unknown: Vec<String>,
+}
+
+impl Decoded {
+    /// 符号を復号した結果を追加する
+    fn push(&mut self, code: &str, candidates: Option<&Vec<char>>) {
+        match candidates {
+            Some(candidates) => {
+                self.text.push(candidates[0]);
+                if 1 < candidates.len() && !self.ambiguous.iter().any(|(c, _)| c == code) {
+                    self.ambiguous.push((code.to_string(), candidates.clone()));
+                }
+            }
+            None => {
+                self.text.push(UNKNOWN);
+                if !self.unknown.iter().any(|c| c == code) {
+                    self.unknown.push(code.to_string());
+                }
+            }
+        }
+    }
+}
+
+/// モールスコード -> 文字の逆変換
+#[derive(Clone, Debug)]
+pub struct Decoder {
+    /// 符号 -> 候補の文字
+    codes: HashMap<String, Vec<char>>,
+    /// 二つの符号で一文字になるもの(和文の濁音・半濁音)
+    pairs: HashMap<(String, String), char>,
+    /// 文字間の区切り
+    letter_separator: String,
+    /// 語間の区切り
+    word_separator: String,
+}
+
+impl Decoder {
+    pub fn new(alphabet: Alphabet, letter_separator: &str, word_separator: &str) -> Decoder {
+        let mut codes: HashMap<String, Vec<char>> = HashMap::new();
+        let mut pairs = HashMap::new();
+
+        for (chars, code) in translation_entries() {
+            // 小文字・ひらがなは代表の文字と同じ符号なので、先頭の文字だけを使う
+            let ch = chars[0];
+            if !alphabet.contains(ch) || code.trim().is_empty() || code == "<" || code == ">" {
+                continue;
+            }
+            match code.split_once(' ') {
+                Some((first, second)) => {
+                    // 欧文の `&` (ES) のような、二文字分の符号は復号しない
+                    if alphabet == Alphabet::Wabun {
+                        pairs.insert((first.to_string(), second.to_string()), ch);
+                    }
+                }
+                None => {
+                    let candidates = codes.entry(code.to_string()).or_default();
+                    if !candidates.contains(&ch) {
+                        candidates.push(ch);
+                    }
+                }
+            }
+        }
+
+        return Decoder {
+            codes,
+            pairs,
+            letter_separator: letter_separator.to_string(),
+            word_separator: word_separator.to_string(),
+        };
+    }
+
+    /// 符号に当たる文字の候補
+    pub fn lookup(&self, code: &str) -> Option<&[char]> {
+        return self.codes.get(code).map(|c| c.as_slice());
+    }
+
+    /// 二つの符号で一文字になる場合、その文字(和文の濁音・半濁音)
+    pub fn lookup_pair(&self, first: &str, second: &str) -> Option<char> {
+        return self
+            .pairs
+            .get(&(first.to_string(), second.to_string()))
+            .copied();
+    }
+
+    /// 次の符号と合わせて一文字になりうる符号か(和文の濁音・半濁音の前半)
+    pub fn is_pair_head(&self, code: &str) -> bool {
+        return self.pairs.keys().any(|(first, _)| first == code);
+    }
+
+    /// 短点・長点の表記(一行)を文字列に復号する
+    ///
+    /// `·` `−` `_` なども短点・長点として受け付ける。
+    /// 和文の濁点・半濁点は前の文字と合わせる。このツールは文字間で送るが、以前の版で作った録音や、
+    /// 濁点の前で間を空ける送り手にも対応するため、語間を一つ挟んでいても合わせる
+    /// (濁点・半濁点で始まる語はないので、取り違えることはない)。
+    pub fn decode(&self, notation: &str) -> Decoded {
+        let mut decoded = Decoded::default();
+        // 符号の並び。`None` は語間
+        let mut codes: Vec<Option<String>> = vec![];
+
+        for word in notation.split(self.word_separator.as_str()) {
+            let mut letters = word
+                .split(self.letter_separator.as_str())
+                .map(normalize)
+                .filter(|c| !c.is_empty())
+                .peekable();
+            if letters.peek().is_some() && !codes.is_empty() {
+                codes.push(None);
+            }
+            codes.extend(letters.map(Some));
+        }
+
+        let mut k = 0;
+        while k < codes.len() {
+            let Some(ref code) = codes[k] else {
+                decoded.text.push(' ');
+                k += 1;
+                continue;
+            };
+            // 次の符号(語間を一つまで飛ばす)と合わせて一文字になるか
+            let skip = usize::from(codes.get(k + 1).is_some_and(|c| c.is_none()));
+            if let Some(ch) = codes
+                .get(k + 1 + skip)
+                .and_then(|next| next.as_ref())
+                .and_then(|next| self.lookup_pair(code, next))
+            {
+                decoded.text.push(ch);
+                k += 2 + skip;
+                continue;
+            }
+            decoded.push(code, self.codes.get(code));
+            k += 1;
+        }
+
+        return decoded;
+    }
+}
+
+/// 短点の長さの推定に使う、直近のキーイングの数
+const HISTORY: usize = 8;
+/// 短点と長点・符号間と文字間の境目[短点]
+const DAH_THRESHOLD: f64 = 2.0;
+/// 文字間と語間の境目[短点]
+const WORD_THRESHOLD: f64 = 5.0;
+/// 送信の区切りとみなす無音[秒]
+pub const BREAK_TIME: f64 = 2.0;
+
+/// キーイングから読み取ったもの
+#[derive(Clone, Debug, PartialEq)]
+pub enum Copied {
+    /// 一文字分の符号(`.-` など)
+    Letter(String),
+    /// 語間
+    Space,
+}
+
+/// キーを押している・離している時間から、符号を読み取る
+///
+/// 短点の長さは直近のキーイングから推定し続けるので、途中で速度が変わっても追従する。
+/// 符号は文字の区切りが分かった時点でまとめて判定する。
+#[derive(Clone, Debug, Default)]
+pub struct Copier {
+    /// 直近のキーを押している時間[秒]
+    downs: VecDeque<f64>,
+    /// 直近のキーを離している時間[秒]
+    ups: VecDeque<f64>,
+    /// 読み取り中の文字のキーを押している時間[秒]
+    elements: Vec<f64>,
+    /// 読み取り中の文字の符号間[秒]
+    gaps: Vec<f64>,
+    /// 文字を区切った後の無音なら、語間の判定に使う短点の長さ[秒]
+    spacing: Option<f64>,
+}
+
+impl Copier {
+    pub fn new() -> Copier {
+        return Copier::default();
+    }
+
+    /// 推定した短点の長さ[秒]
+    ///
+    /// キーを押している時間に 2 倍以上の差があれば、短い方を短点とする。
+    /// 差がなければ、すべて短点かすべて長点なので、符号間(短点の長さ)と比べて決める。
+    /// 音の検出では押している時間が短く、離している時間が長くなるので、符号間と平均する。
+    pub fn dit(&self) -> Option<f64> {
+        let short = self.downs.iter().copied().reduce(f64::min)?;
+        let long = self.downs.iter().copied().reduce(f64::max)?;
+
+        let dit = if DAH_THRESHOLD * short <= long {
+            mean(
+                self.downs
+                    .iter()
+                    .copied()
+                    .filter(|d| *d < (short * long).sqrt()),
+            )
+        } else {
+            let m = mean(self.downs.iter().copied());
+            if self.ups.iter().any(|u| DAH_THRESHOLD * u < m) {
+                m / 3.0
+            } else {
+                m
+            }
+        };
+
+        return match mean(
+            self.ups
+                .iter()
+                .copied()
+                .filter(|u| *u < DAH_THRESHOLD * dit),
+        ) {
+            gap if 0.0 < gap => Some((dit + gap) / 2.0),
+            _ => Some(dit),
+        };
+    }
+
+    /// 推定した速度[wpm] (PARIS 基準)
+    pub fn wpm(&self) -> Option<f64> {
+        return self.dit().map(|dit| 1.2 / dit);
+    }
+
+    /// 短点の長さの推定にだけ使うキーイングを加える
+    ///
+    /// 録音全体が手元にあれば、先のキーイングを先に聞いておくことで、速度の変化にすぐ追従できる。
+    pub fn listen(&mut self, key_down: bool, duration: f64) {
+        let history = if key_down {
+            &mut self.downs
+        } else {
+            &mut self.ups
+        };
+        if history.len() == HISTORY {
+            history.pop_front();
+        }
+        history.push_back(duration);
+    }
+
+    /// キーイングを加え、文字や語間が読み取れたら返す
+    pub fn push(&mut self, key_down: bool, duration: f64) -> Vec<Copied> {
+        self.listen(key_down, duration);
+        return self.copy(key_down, duration);
+    }
+
+    /// キーイングを読み取り、文字や語間が読み取れたら返す(短点の長さの推定には加えない)
+    pub fn copy(&mut self, key_down: bool, duration: f64) -> Vec<Copied> {
+        if key_down {
+            self.elements.push(duration);
+            return vec![];
+        }
+        if let Some(dit) = self.letter_dit() {
+            if duration < DAH_THRESHOLD * dit {
+                self.gaps.push(duration);
+                return vec![];
+            }
+        }
+
+        let copied = self.silence(duration);
+        self.spacing = None;
+        return copied;
+    }
+
+    /// キーを離してから `duration` [秒]経った時点で、読み取れた文字や語間を返す
+    ///
+    /// 同じ無音について何度呼んでもよい。ストリームで、次にキーを押すのを待たずに文字を確定するために使う。
+    pub fn silence(&mut self, duration: f64) -> Vec<Copied> {
+        let mut copied = vec![];
+
+        if let Some(dit) = self.letter_dit() {
+            if duration < DAH_THRESHOLD * dit {
+                return copied;
+            }
+            copied.extend(self.flush());
+            self.spacing = Some(dit);
+        }
+        if let Some(dit) = self.spacing {
+            if WORD_THRESHOLD * dit <= duration {
+                copied.push(Copied::Space);
+                self.spacing = None;
+            }
+        }
+
+        return copied;
+    }
+
+    /// 読み取り中の文字の、符号間・文字間の判定に使う短点の長さ[秒]
+    ///
+    /// 文字の途中であれば、その文字の符号間を短点の長さとする(急な速度の変化に追従するため)。
+    /// 音の検出では符号間が長くなるので、文字の中の短点があれば平均する。
+    /// 直前の符号が長点なら、その 1/3 より短点を短くは見積もらない。
+    fn letter_dit(&self) -> Option<f64> {
+        let last = *self.elements.last()?;
+
+        return match mean(self.gaps.iter().copied()) {
+            gap if 0.0 < gap => {
+                match mean(
+                    self.elements
+                        .iter()
+                        .copied()
+                        .filter(|d| *d < DAH_THRESHOLD * gap),
+                ) {
+                    dit if 0.0 < dit => Some((dit + gap) / 2.0),
+                    _ => Some(gap),
+                }
+            }
+            _ => Some(self.dit().unwrap_or(last).max(last / 3.0)),
+        };
+    }
+
+    /// 読み取り中の文字を区切って返す
+    ///
+    /// 短点と長点が混じっていれば、その中で分ける。
+    /// そろっていれば、符号間か推定した短点の長さと比べる。
+    pub fn flush(&mut self) -> Option<Copied> {
+        if self.elements.is_empty() {
+            return None;
+        }
+        let short = self.elements.iter().copied().fold(f64::MAX, f64::min);
+        let long = self.elements.iter().copied().fold(0.0, f64::max);
+        let threshold = if DAH_THRESHOLD * short <= long {
+            (short * long).sqrt()
+        } else {
+            let dit = match mean(self.gaps.iter().copied()) {
+                gap if 0.0 < gap => gap,
+                _ => self.dit()?,
+            };
+            DAH_THRESHOLD * dit
+        };
+
+        let code = self
+            .elements
+            .drain(..)
+            .map(|d| if d < threshold { '.' } else { '-' })
+            .collect();
+        self.gaps.clear();
+
+        return Some(Copied::Letter(code));
+    }
+}
+
+impl Decoder {
+    /// キーイング(キーを押しているか、続いた時間[秒])を文字列に復号する
+    ///
+    /// 短点の長さは、前後のキーイング(先読み)から推定する。
+    pub fn decode_keying(&self, keying: &[(bool, f64)]) -> Decoded {
+        let mut copier = Copier::new();
+        let mut copied = vec![];
+
+        for (key_down, duration) in keying.iter().take(HISTORY) {
+            copier.listen(*key_down, *duration);
+        }
+        for (k, (key_down, duration)) in keying.iter().enumerate() {
+            if let Some((key_down, duration)) = keying.get(k + HISTORY) {
+                copier.listen(*key_down, *duration);
+            }
+            copied.extend(copier.copy(*key_down, *duration));
+        }
+        copied.extend(copier.flush());
+
+        return self.decode_copied(&copied);
+    }
+
+    /// 読み取った符号を文字列に復号する
+    pub fn decode_copied(&self, copied: &[Copied]) -> Decoded {
+        let mut notation = String::new();
+        for c in copied {
+            match c {
+                Copied::Letter(code) => {
+                    notation.push_str(code);
+                    notation.push_str(&self.letter_separator);
+                }
+                Copied::Space => notation.push_str(&self.word_separator),
+            }
+        }
+
+        return self.decode(&notation);
+    }
+}
+
+/// 平均(空なら 0)
+fn mean(values: impl Iterator<Item = f64>) -> f64 {
+    let (sum, count) = values.fold((0.0, 0), |(s, c), v| (s + v, c + 1));
+    if count == 0 {
+        return 0.0;
+    }
+    return sum / count as f64;
+}
+
+/// 短点・長点の表記をそろえる
+fn normalize(code: &str) -> String {
+    return code
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '·' | '•' | '*' => '.',
+            '−' | '–' | '_' => '-',
+            c => c,
+        })
+        .collect();
+}
@@ -0,0 +1,321 @@
+use std::f32::consts::PI;
+
+/// 探す音の周波数の範囲[Hz]
+const SEARCH_LOW: f32 = 200.0;
+const SEARCH_HIGH: f32 = 2000.0;
+/// 音を探すのに使う長さ[秒]
+const SEARCH_TIME: f32 = 10.0;
+
+/// 複数の信号を探すブロックの長さ[秒] (窓を掛けて、周波数の分解能を 2/0.04 = 50Hz にする)
+const SCAN_TIME: f32 = 0.04;
+/// 複数の信号を探す周波数の刻み[Hz]
+const SCAN_STEP: f32 = 10.0;
+/// 別の信号とみなす、最小の周波数の差[Hz]
+const MIN_SPACING: f32 = 50.0;
+/// 信号とみなす強さ(雑音の中央値との電力比、6dB)
+const DETECT_RATIO: f32 = 4.0;
+/// 最も強い信号に対する、信号とみなす強さの下限(電力比、-25dB。窓のサイドローブを拾わないため)
+const DYNAMIC_RANGE: f32 = 0.003;
+
+/// 検出の単位とするブロックの長さ[秒]
+pub const BLOCK_TIME: f32 = 0.005;
+/// キーの状態が変わったとみなす、最短の継続ブロック数
+const MIN_RUN: usize = 2;
+/// 信号の強さの最大値・最小値が戻っていく時定数[秒]
+const TRACK_TIME: f32 = 2.0;
+
+/// 一つの周波数の強さを求める(Goertzel アルゴリズム)
+#[derive(Clone, Debug)]
+pub struct Goertzel {
+    /// 2cos(2πf/fs)
+    coefficient: f32,
+    /// ブロックのサンプル数
+    block: usize,
+    s1: f32,
+    s2: f32,
+    /// ブロック内の位置
+    count: usize,
+}
+
+impl Goertzel {
+    pub fn new(frequency: f32, sample_rate: u32, block: usize) -> Goertzel {
+        return Goertzel {
+            coefficient: 2.0 * (2.0 * PI * frequency / sample_rate as f32).cos(),
+            block: block.max(1),
+            s1: 0.0,
+            s2: 0.0,
+            count: 0,
+        };
+    }
+
+    /// サンプルを加え、ブロックがそろったらその振幅(正弦波の振幅に換算)を返す
+    pub fn push(&mut self, x: f32) -> Option<f32> {
+        let s0 = x + self.coefficient * self.s1 - self.s2;
+        self.s2 = self.s1;
+        self.s1 = s0;
+        self.count += 1;
+
+        if self.count < self.block {
+            return None;
+        }
+
+        let power = self.s1 * self.s1 + self.s2 * self.s2 - self.coefficient * self.s1 * self.s2;
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+        self.count = 0;
+
+        return Some(2.0 * power.max(0.0).sqrt() / self.block as f32);
+    }
+}
+
+/// 録音の中で最も強い音の周波数[Hz]を探す
+///
+/// 先頭から 10 秒までを 10 Hz 刻みで調べ、前後 10 Hz を 1 Hz 刻みで詰める。
+pub fn find_tone(samples: &[f32], sample_rate: u32) -> Option<f32> {
+    let samples = &samples[..samples
+        .len()
+        .min((SEARCH_TIME * sample_rate as f32) as usize)];
+    if samples.is_empty() {
+        return None;
+    }
+    let high = SEARCH_HIGH.min(0.45 * sample_rate as f32);
+
+    let strongest = |frequencies: &mut dyn Iterator<Item = f32>| -> Option<(f32, f32)> {
+        return frequencies
+            .map(|f| (f, level(samples, f, sample_rate)))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+    };
+
+    let mut coarse = (0..)
+        .map(|k| SEARCH_LOW + 10.0 * k as f32)
+        .take_while(|f| *f <= high);
+    let (center, _) = strongest(&mut coarse)?;
+    let mut fine = (-10..=10).map(|k| center + k as f32);
+    let (frequency, power) = strongest(&mut fine)?;
+
+    if power <= 0.0 {
+        return None;
+    }
+    return Some(frequency);
+}
+
+/// 雑音より強い信号があれば、最も強い音の周波数[Hz]を返す
+///
+/// 受信しながら音を探すときに、雑音しかない間に周波数を決めてしまわないようにする。
+pub fn detect_tone(samples: &[f32], sample_rate: u32) -> Option<f32> {
+    if find_signals(samples, sample_rate, SEARCH_LOW, SEARCH_HIGH).is_empty() {
+        return None;
+    }
+    return find_tone(samples, sample_rate);
+}
+
+/// 録音の中の信号(CW の音)の周波数[Hz]を、`low` .. `high` の範囲ですべて探す
+///
+/// 録音全体の強さを 10 Hz 刻みで調べ、雑音より強い山をそれぞれ一つの信号とする。
+/// 山の頂点は前後の強さから補間する。周波数の低い順に返す。
+pub fn find_signals(samples: &[f32], sample_rate: u32, low: f32, high: f32) -> Vec<f32> {
+    let block = (SCAN_TIME * sample_rate as f32) as usize;
+    let high = high.min(0.45 * sample_rate as f32);
+    let frequencies: Vec<f32> = (0..)
+        .map(|k| low + SCAN_STEP * k as f32)
+        .take_while(|f| *f <= high)
+        .collect();
+    if frequencies.len() < 3 || samples.len() < block {
+        return vec![];
+    }
+
+    // ハン窓を掛けたブロック毎の強さを積算する
+    let window: Vec<f32> = (0..block)
+        .map(|n| 0.5 - 0.5 * (2.0 * PI * n as f32 / block as f32).cos())
+        .collect();
+    let mut goertzels: Vec<Goertzel> = frequencies
+        .iter()
+        .map(|f| Goertzel::new(*f, sample_rate, block))
+        .collect();
+    let mut spectrum = vec![0.0_f32; frequencies.len()];
+    for chunk in samples.chunks_exact(block) {
+        for (goertzel, power) in goertzels.iter_mut().zip(spectrum.iter_mut()) {
+            if let Some(a) = chunk
+                .iter()
+                .zip(&window)
+                .filter_map(|(x, w)| goertzel.push(x * w))
+                .last()
+            {
+                *power += a * a;
+            }
+        }
+    }
+
+    let mut sorted = spectrum.clone();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let noise = sorted[sorted.len() / 2];
+    let strongest = sorted[sorted.len() - 1];
+    let threshold = (DETECT_RATIO * noise).max(DYNAMIC_RANGE * strongest);
+
+    // 強い山から順に、近くに採用済みの信号がなければ採用する
+    let mut peaks: Vec<(f32, f32)> = (1..spectrum.len() - 1)
+        .filter(|k| {
+            spectrum[k - 1] < spectrum[*k]
+                && spectrum[k + 1] <= spectrum[*k]
+                && threshold < spectrum[*k]
+        })
+        .map(|k| {
+            let (a, b, c) = (spectrum[k - 1], spectrum[k], spectrum[k + 1]);
+            let delta = 0.5 * (a - c) / (a - 2.0 * b + c);
+            ((frequencies[k] + delta * SCAN_STEP).round(), b)
+        })
+        .collect();
+    peaks.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let mut signals: Vec<f32> = vec![];
+    for (frequency, _) in peaks {
+        if signals.iter().all(|f| MIN_SPACING <= (f - frequency).abs()) {
+            signals.push(frequency);
+        }
+    }
+    signals.sort_by(|a, b| a.total_cmp(b));
+
+    return signals;
+}
+
+/// 周波数 `frequency` の強さ(ブロック毎の振幅の二乗和)
+fn level(samples: &[f32], frequency: f32, sample_rate: u32) -> f32 {
+    let block = (BLOCK_TIME * 4.0 * sample_rate as f32) as usize;
+    let mut goertzel = Goertzel::new(frequency, sample_rate, block);
+
+    return samples
+        .iter()
+        .filter_map(|x| goertzel.push(*x))
+        .map(|a| a * a)
+        .sum();
+}
+
+/// 一つの周波数の音の有無から、キーを押している・離している時間を求める
+///
+/// 閾値は、信号の強さの最大値と最小値の中間に追従させる。
+/// 短すぎる変化(雑音)は無視する。
+#[derive(Clone, Debug)]
+pub struct ToneDetector {
+    goertzel: Goertzel,
+    /// ブロックの長さ[秒]
+    block_time: f64,
+    /// 最大値・最小値の追従の係数(ブロック毎)
+    decay: f32,
+    /// 信号の強さの最大値
+    peak: f32,
+    /// 信号の強さの最小値
+    floor: f32,
+    /// 現在のキーの状態
+    key_down: bool,
+    /// 現在の状態が続いているブロック数
+    run: usize,
+    /// 反対の状態が続いているブロック数
+    pending: usize,
+    /// これより弱い信号は音とみなさない
+    squelch: f32,
+}
+
+impl ToneDetector {
+    pub fn new(frequency: f32, sample_rate: u32, block_time: f32) -> ToneDetector {
+        let block = (block_time * sample_rate as f32).round() as usize;
+        let block_time = block.max(1) as f64 / sample_rate as f64;
+
+        return ToneDetector {
+            goertzel: Goertzel::new(frequency, sample_rate, block),
+            block_time,
+            decay: (-(block_time as f32) / TRACK_TIME).exp(),
+            peak: 0.0,
+            floor: f32::MAX,
+            key_down: false,
+            run: 0,
+            pending: 0,
+            squelch: 0.0,
+        };
+    }
+
+    /// これより弱い信号(振幅)は音とみなさないようにする
+    ///
+    /// 雑音のない録音では、長い無音の間に最大値が下がり、隣の信号の漏れを音と取り違えるのを防ぐ。
+    pub fn set_squelch(&mut self, level: f32) {
+        self.squelch = level;
+    }
+
+    /// サンプルを加え、キーの状態が変わったら、それまでの状態と続いた時間[秒]を返す
+    pub fn push(&mut self, x: f32) -> Option<(bool, f64)> {
+        let amplitude = self.goertzel.push(x)?;
+
+        // 最大値・最小値は、超えたら即座に追従し、その後はゆっくり戻す
+        self.peak = amplitude.max(self.peak * self.decay + amplitude * (1.0 - self.decay));
+        self.floor = amplitude.min(self.floor * self.decay + amplitude * (1.0 - self.decay));
+
+        // 最小値より 6dB 以上強い信号がなければ、音はないとみなす
+        let threshold = (self.peak + self.floor) / 2.0;
+        let is_tone =
+            2.0 * self.floor < self.peak && threshold < amplitude && self.squelch < amplitude;
+
+        if is_tone == self.key_down {
+            self.run += self.pending + 1;
+            self.pending = 0;
+            return None;
+        }
+
+        self.pending += 1;
+        if self.pending < MIN_RUN {
+            return None;
+        }
+
+        let finished = (self.key_down, self.run as f64 * self.block_time);
+        self.key_down = is_tone;
+        self.run = self.pending;
+        self.pending = 0;
+
+        if finished.1 == 0.0 {
+            return None;
+        }
+        return Some(finished);
+    }
+
+    /// 終端に達したときに、最後の状態と続いた時間[秒]を返す
+    pub fn finish(&mut self) -> Option<(bool, f64)> {
+        let run = self.run + self.pending;
+        self.run = 0;
+        self.pending = 0;
+
+        if run == 0 {
+            return None;
+        }
+        return Some((self.key_down, run as f64 * self.block_time));
+    }
+
+    /// 現在のキーの状態と、続いている時間[秒]
+    pub fn state(&self) -> (bool, f64) {
+        return (self.key_down, self.run as f64 * self.block_time);
+    }
+
+    /// 録音の先頭の信号の強さから、最大値・最小値を決めておく
+    ///
+    /// 録音がいきなり音で始まっても、最初の符号を取りこぼさないようにする。
+    pub fn prime(&mut self, samples: &[f32]) {
+        let sample_rate = (self.goertzel.block as f64 / self.block_time).round() as usize;
+        let mut goertzel = self.goertzel.clone();
+
+        for amplitude in samples
+            .iter()
+            .take(SEARCH_TIME as usize * sample_rate)
+            .filter_map(|x| goertzel.push(*x))
+        {
+            self.peak = self.peak.max(amplitude);
+            self.floor = self.floor.min(amplitude);
+        }
+    }
+
+    /// 録音全体のキーイングを求める
+    pub fn keying(&mut self, samples: &[f32]) -> Vec<(bool, f64)> {
+        self.prime(samples);
+
+        let mut keying: Vec<(bool, f64)> = samples.iter().filter_map(|x| self.push(*x)).collect();
+        keying.extend(self.finish());
+
+        return keying;
+    }
+}
@@ -0,0 +1,271 @@
+use std::{collections::HashMap, io::BufRead};
+
+use anyhow::Result;
+
+use crate::{
+    event::{Event, Events, GapKind},
+    translation_table::set_translation_table,
+};
+
+/// 符号を表記する記号
+#[derive(Clone, Debug, PartialEq)]
+pub enum Symbols {
+    /// 短点と長点の記号(`.` `-` や `·` `−` など)
+    Marks { dot: String, dash: String },
+    /// 口で唱える読み方(`di-dah` など)
+    Phonetic,
+}
+
+/// 一文字の符号
+#[derive(Clone, Debug, PartialEq)]
+pub struct Letter {
+    /// 文字
+    pub ch: char,
+    /// 表記した符号
+    pub code: String,
+    /// 送り始める時刻[秒]
+    pub start: f64,
+}
+
+/// 一行分の符号
+#[derive(Clone, Debug, PartialEq)]
+pub struct Encoded {
+    /// 行の電文
+    pub text: String,
+    /// 送るプレイヤー
+    pub player: String,
+    /// 送り始める速度[wpm](和文の割合とランプの倍率を掛けたもの)
+    pub wpm: f64,
+    /// 送り始める時刻[秒]
+    pub start: f64,
+    /// 送るのにかかる時間[秒]
+    pub duration: f64,
+    /// 行全体の符号
+    pub code: String,
+    /// 文字毎の符号
+    pub letters: Vec<Letter>,
+}
+
+impl Encoded {
+    /// 一行の JSON にする
+    pub fn to_json(&self) -> String {
+        let letters: Vec<String> = self
+            .letters
+            .iter()
+            .map(|l| {
+                format!(
+                    "{{\"char\":{},\"code\":{},\"start\":{:.3}}}",
+                    json_string(&l.ch.to_string()),
+                    json_string(&l.code),
+                    l.start
+                )
+            })
+            .collect();
+
+        return format!(
+            "{{\"text\":{},\"player\":{},\"wpm\":{},\"start\":{:.3},\"duration\":{:.3},\"code\":{},\"letters\":[{}]}}",
+            json_string(&self.text),
+            json_string(&self.player),
+            self.wpm,
+            self.start,
+            self.duration,
+            json_string(&self.code),
+            letters.join(",")
+        );
+    }
+}
+
+/// JSON の文字列にする(引用符で囲み、エスケープする)
+fn json_string(s: &str) -> String {
+    let mut escaped = String::from("\"");
+
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+
+    return escaped;
+}
+
+/// 電文を短点・長点の表記にする
+///
+/// 発音と同じ出来事から作るので、`<...>` の一文字化や `#!` 行もそのまま反映される。
+pub struct Encoder {
+    /// 文字->モールス符号変換用テーブル
+    table: HashMap<char, &'static str>,
+    /// 表記に使う記号
+    symbols: Symbols,
+    /// 文字間の区切り
+    letter_separator: String,
+    /// 語間の区切り
+    word_separator: String,
+}
+
+impl Encoder {
+    pub fn new(symbols: Symbols, letter_separator: &str, word_separator: &str) -> Encoder {
+        return Encoder {
+            table: set_translation_table(),
+            symbols,
+            letter_separator: letter_separator.to_string(),
+            word_separator: word_separator.to_string(),
+        };
+    }
+
+    /// 一文字の符号を表記する。符号がない文字(空白や `<` `>` など)は `None`
+    ///
+    /// `&` (ES) のように二文字分の符号は、文字間の区切りでつなぐ。
+    pub fn code(&self, ch: char) -> Option<String> {
+        let code = self.table.get(&ch)?;
+        if !code.contains(['.', '-']) {
+            return None;
+        }
+
+        let letters: Vec<String> = code
+            .split(' ')
+            .map(|letter| match self.symbols {
+                Symbols::Marks { ref dot, ref dash } => letter
+                    .chars()
+                    .map(|c| {
+                        if c == '.' {
+                            dot.as_str()
+                        } else {
+                            dash.as_str()
+                        }
+                    })
+                    .collect(),
+                Symbols::Phonetic => {
+                    // 最後の短点だけは "dit" と読む
+                    let last = letter.len() - 1;
+                    let elements: Vec<&str> = letter
+                        .chars()
+                        .enumerate()
+                        .map(|(i, c)| match c {
+                            '-' => "dah",
+                            _ if i == last => "dit",
+                            _ => "di",
+                        })
+                        .collect();
+                    elements.join("-")
+                }
+            })
+            .collect();
+
+        return Some(letters.join(&self.letter_separator));
+    }
+
+    /// キーイングの出来事から、一行毎の符号を順に返す
+    pub fn encode<'a, R: BufRead>(&'a self, events: Events<'a, R>) -> Encoding<'a, R> {
+        return Encoding {
+            encoder: self,
+            events,
+            player: "default".to_string(),
+            last_start: 0.0,
+            end: 0.0,
+            gap: 0.0,
+        };
+    }
+}
+
+/// 一行毎の符号を返すイテレーター
+///
+/// 時刻は発音したときと同じく、`#! --offset` で重ねた行も考慮する。
+pub struct Encoding<'a, R> {
+    encoder: &'a Encoder,
+    events: Events<'a, R>,
+    /// 現在のプレイヤー
+    player: String,
+    /// 前の行の開始時刻(行の前の語間を含む)[秒]
+    last_start: f64,
+    /// 送り終わる時刻[秒]
+    end: f64,
+    /// 行の前の語間[秒]
+    gap: f64,
+}
+
+impl<R: BufRead> Iterator for Encoding<'_, R> {
+    type Item = Result<Encoded>;
+
+    fn next(&mut self) -> Option<Result<Encoded>> {
+        let encoder = self.encoder;
+        let mut line: Option<Encoded> = None;
+        // 前の文字との間の区切り
+        let mut separator: Option<&str> = None;
+
+        for event in self.events.by_ref() {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => return Some(Err(e)),
+            };
+
+            match (event, line.as_mut()) {
+                (Event::PlayerChange(name), _) => self.player = name,
+                (Event::LineStart { text, offset, wpm }, _) => {
+                    let start = match offset {
+                        // 前の行の開始から指定時間後に、重ねて送る
+                        Some(offset) => self.last_start + offset,
+                        None => self.end,
+                    };
+                    self.last_start = start;
+                    let start = if offset.is_none() {
+                        start + self.gap
+                    } else {
+                        start
+                    };
+                    self.gap = 0.0;
+
+                    line = Some(Encoded {
+                        text,
+                        player: self.player.clone(),
+                        wpm,
+                        start,
+                        duration: 0.0,
+                        code: String::new(),
+                        letters: vec![],
+                    });
+                    separator = None;
+                }
+                (Event::CharStart(ch), Some(l)) => {
+                    if ch == ' ' {
+                        separator = Some(&encoder.word_separator);
+                    }
+                    if let Some(code) = encoder.code(ch) {
+                        if !l.code.is_empty() {
+                            l.code.push_str(separator.unwrap_or(""));
+                        }
+                        l.code.push_str(&code);
+                        l.letters.push(Letter {
+                            ch,
+                            code,
+                            start: l.start + l.duration,
+                        });
+                        separator = None;
+                    }
+                }
+                (Event::Gap { kind, duration }, Some(l)) => {
+                    // `<...>` で一文字化した文字の間には文字間が入らない
+                    if kind == GapKind::Letter && separator.is_none() {
+                        separator = Some(&encoder.letter_separator);
+                    }
+                    l.duration += duration;
+                }
+                (Event::Gap { duration, .. }, None) => self.gap += duration,
+                (Event::ToneOn { duration }, Some(l)) => l.duration += duration,
+                (Event::LineEnd, Some(l)) => {
+                    self.end = self.end.max(l.start + l.duration);
+                    return line.map(Ok);
+                }
+                _ => {}
+            }
+        }
+
+        return None;
+    }
+}
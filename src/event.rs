@@ -0,0 +1,333 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{BufRead, Lines},
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    player::{LineOption, Player},
+    random::{player_seed, Rng},
+    timing::{Ramp, RampUnit, Timing},
+    translation_table::is_kana,
+};
+
+/// 間隔の種類
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GapKind {
+    /// 符号(短点・長点)の後の間隔
+    Element,
+    /// 文字間
+    Letter,
+    /// 語間(行の間を含む)
+    Word,
+}
+
+/// キーイングの出来事
+///
+/// 時間はすべて秒単位。`<...>` で一文字化した文字の間には `Gap` が入らない。
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// `#!` 行のオプションを反映した
+    LineOption(Box<LineOption>),
+    /// `#! --player` でプレイヤーを登録または切り替えた
+    PlayerChange(String),
+    /// 行の始まり。`offset` があれば、前の行の開始から `offset` [秒]後に重ねて送る
+    ///
+    /// `wpm` は最初の文字を送る速度(和文の割合とランプの倍率を掛けたもの)。
+    LineStart {
+        text: String,
+        offset: Option<f64>,
+        wpm: f64,
+    },
+    /// 文字の始まり
+    CharStart(char),
+    /// キーを押している
+    ToneOn { duration: f64 },
+    /// キーを離している
+    Gap { kind: GapKind, duration: f64 },
+    /// 行の終わり
+    LineEnd,
+}
+
+/// 和文(かなを含む行)か
+fn is_wabun(text: &str) -> bool {
+    return text.chars().any(is_kana);
+}
+
+/// 行を送る符号の長さの決まり
+///
+/// ランプの倍率を掛けて速度の範囲に収めてから、和文は欧文より遅く送る。
+fn line_timing(timing: &Timing, factor: f64, is_wabun: bool) -> Timing {
+    let timing = timing.ramped(factor);
+    if is_wabun {
+        return timing.for_wabun();
+    }
+    return timing;
+}
+
+/// 電文(複数行)を解釈して、キーイングの出来事を順に返すイテレーター
+///
+/// `#!` 行はその場で解釈し、以降の行の速度や手送りの癖に反映する。
+/// 入力は一行ずつ読むので、標準入力のように終わりのない入力にも使える。
+pub struct Events<'a, R> {
+    /// 文字->モールス音変換用テーブル
+    table: &'a HashMap<char, &'static str>,
+    /// 入力
+    lines: Lines<R>,
+
+    /// 既定のプレイヤー(シードの元)
+    default: Player,
+    /// 現在のプレイヤー
+    player: Player,
+    /// 登録されたプレイヤー
+    players: HashMap<String, Player>,
+
+    /// 最初の行か
+    is_first: bool,
+    /// 次の行を前の行に重ねて発音する場合の開始位置[秒]
+    offset: Option<f64>,
+    /// 手送りの揺らぎ(雑音とは別の系列にする)
+    rng: Rng,
+    /// 速度の変え方
+    ramp: Ramp,
+    /// 最後の符号の後の、まだ出来事にしていない間隔と、その符号の短点の長さ[秒]
+    held: Option<(f64, f64)>,
+
+    /// 返していない出来事
+    pending: VecDeque<Event>,
+    /// 入力の終わりかエラーに達したか
+    is_done: bool,
+}
+
+impl<'a, R: BufRead> Events<'a, R> {
+    pub fn new(
+        table: &'a HashMap<char, &'static str>,
+        player: Player,
+        ramp: Ramp,
+        reader: R,
+    ) -> Events<'a, R> {
+        return Events {
+            table,
+            lines: reader.lines(),
+            default: player.clone(),
+            players: HashMap::from([("default".to_string(), player.clone())]),
+            rng: Rng::new(!player.condition.seed),
+            player,
+            is_first: true,
+            offset: None,
+            ramp,
+            held: None,
+            pending: VecDeque::new(),
+            is_done: false,
+        };
+    }
+
+    /// 直前に返した出来事を送っているプレイヤー
+    pub fn player(&self) -> &Player {
+        return &self.player;
+    }
+
+    /// 一行を解釈して、出来事を追加する
+    fn interpret(&mut self, mut line: String) -> Result<()> {
+        if let Some(l) = line.strip_prefix("#!") {
+            // 行頭がオプション定義ならオプションとして解釈
+            if l.is_empty() {
+                return Ok(());
+            }
+            let o = LineOption::parse(l)?;
+
+            self.player.apply(&o)?;
+            if o.offset.is_some() {
+                self.offset = o.offset;
+            }
+
+            if let Some(ref w) = o.player {
+                if !o.has_settings() {
+                    // すべて指定なしの場合は、定義されたプレイヤーを参照する
+                    if let Some(p) = self.players.get(w) {
+                        self.player = p.clone();
+                    } else {
+                        return Err(anyhow!("Warning: `player`({}) is not defined.", w));
+                    }
+                } else {
+                    // どれか一つでも指定されているのなら、'player'定義として登録
+                    // 重ねて発音したときに同じようにフェージングしないよう、シードはプレイヤー毎に変える
+                    self.player.condition.seed = player_seed(self.default.condition.seed, w);
+                    self.players.insert(w.to_string(), self.player.clone());
+                }
+                self.pending.push_back(Event::PlayerChange(w.to_string()));
+            }
+            self.pending.push_back(Event::LineOption(Box::new(o)));
+
+            return Ok(());
+        }
+
+        // '#'以降の文字列をコメントとして破棄
+        if let Some(l) = line.split('#').next() {
+            line = l.trim().to_string();
+        }
+        if line.is_empty() {
+            // 空行は無視
+            return Ok(());
+        }
+
+        let offset = self.offset.take();
+        if offset.is_some() || self.is_first {
+            self.is_first = false;
+            self.held = None;
+        } else {
+            let timing = self.player.timing.ramped(self.ramp.factor());
+            let gap = match self.held.take() {
+                // 前の行の最後の符号の後の間隔を含めて、語間にする
+                Some((up, dit)) => {
+                    up + self.player.fist.gap(timing.word_gap() - dit, &mut self.rng)
+                }
+                None => self.player.fist.gap(timing.word_gap(), &mut self.rng),
+            };
+            self.pending.push_back(Event::Gap {
+                kind: GapKind::Word,
+                duration: gap,
+            });
+            self.ramp.advance(RampUnit::Time, gap);
+        }
+
+        self.pending.push_back(Event::LineStart {
+            text: line.clone(),
+            offset,
+            wpm: line_timing(&self.player.timing, self.ramp.factor(), is_wabun(&line)).wpm,
+        });
+        self.play_sound(&line);
+        self.pending.push_back(Event::LineEnd);
+
+        return Ok(());
+    }
+
+    /// テキストをモールス符号に変換、出来事を追加する
+    ///
+    /// 符号の後の間隔は次の符号まで保留し、符号間(1短点)のまま、または文字間(3短点)・語間(7短点)に
+    /// 伸ばして出来事にする。行の最後の符号の後の間隔は、次の行の前の語間に含める。
+    /// 符号や間隔の長さは、プレイヤーの手送りの癖に従って揺らす。
+    /// 速度は文字毎にランプの倍率を掛けて決める。
+    fn play_sound(&mut self, text: &str) {
+        let is_wabun = is_wabun(text);
+        let player = &self.player;
+        let rng = &mut self.rng;
+        let mut is_one = false;
+        // 前の文字と `<...>` で一文字化されているか
+        let mut is_joined = false;
+
+        for ch in text.chars() {
+            let timing = line_timing(&player.timing, self.ramp.factor(), is_wabun);
+            // この文字(前の間隔を含む)の長さ[秒]
+            let mut duration = 0.0;
+
+            let Some(m) = self.table.get(&ch) else {
+                continue;
+            };
+            if *m == " " {
+                // 前の符号の後の間隔を含めて、語間にする
+                let gap = match self.held.take() {
+                    Some((up, dit)) => up + player.fist.gap(timing.word_gap() - dit, rng),
+                    None => player.fist.gap(timing.word_gap(), rng),
+                };
+                self.pending.push_back(Event::CharStart(ch));
+                self.pending.push_back(Event::Gap {
+                    kind: GapKind::Word,
+                    duration: gap,
+                });
+                duration += gap;
+                is_joined = false;
+            } else {
+                // 次の符号の前の間隔の種類。`<...>` の中では文字の間も符号間のまま
+                let mut kind = if is_joined {
+                    GapKind::Element
+                } else {
+                    GapKind::Letter
+                };
+                let mut is_started = false;
+
+                for c in m.chars() {
+                    match c {
+                        '.' | '-' => {
+                            if let Some((up, dit)) = self.held.take() {
+                                let gap = match kind {
+                                    GapKind::Element => up,
+                                    _ => up + player.fist.gap(timing.letter_gap() - dit, rng),
+                                };
+                                self.pending.push_back(Event::Gap {
+                                    kind,
+                                    duration: gap,
+                                });
+                                duration += gap;
+                            }
+                            if !is_started {
+                                self.pending.push_back(Event::CharStart(ch));
+                                is_started = true;
+                            }
+
+                            let (down, up) = player.fist.element(
+                                c == '-',
+                                timing.element(c == '-'),
+                                timing.dit(),
+                                rng,
+                            );
+                            self.pending.push_back(Event::ToneOn { duration: down });
+                            duration += down;
+                            self.held = Some((up, timing.dit()));
+                            kind = GapKind::Element;
+                        }
+                        ' ' => {
+                            // `&` (ES) のように二文字分の符号は、文字間で区切る
+                            kind = GapKind::Letter;
+                        }
+                        '<' => {
+                            is_one = true;
+                        }
+                        '>' => {
+                            is_one = false;
+                        }
+                        _ => {}
+                    }
+                }
+                if !is_started {
+                    self.pending.push_back(Event::CharStart(ch));
+                }
+                // `<` 自体は符号がないので、その前の文字とはつながない。
+                // `<...>` の中で符号のある文字に続く文字だけを一文字にする
+                is_joined = is_one && (is_started || is_joined);
+            }
+
+            self.ramp.advance(RampUnit::Char, 1.0);
+            self.ramp.advance(RampUnit::Time, duration);
+        }
+        self.ramp.advance(RampUnit::Line, 1.0);
+    }
+}
+
+impl<R: BufRead> Iterator for Events<'_, R> {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Result<Event>> {
+        while self.pending.is_empty() {
+            if self.is_done {
+                return None;
+            }
+            let result = match self.lines.next() {
+                Some(Ok(line)) => self.interpret(line),
+                Some(Err(e)) => Err(e.into()),
+                None => {
+                    self.is_done = true;
+                    return None;
+                }
+            };
+            if let Err(e) = result {
+                // エラー以降は解釈しない
+                self.is_done = true;
+                return Some(Err(e));
+            }
+        }
+
+        return self.pending.pop_front().map(Ok);
+    }
+}
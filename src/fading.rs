@@ -0,0 +1,70 @@
+use std::f64::consts::PI;
+
+use clap::ValueEnum;
+
+use crate::random::Rng;
+
+/// フェージング(QSB)の種類
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Fading {
+    /// フェージングなし
+    #[default]
+    None,
+    /// 周期的にゆっくり強弱を繰り返す
+    Sine,
+    /// 多数の経路の干渉による不規則な強弱(レイリー・フェージング)
+    Rayleigh,
+}
+
+/// レイリー・フェージングの合成に使う経路の数
+const PATHS: usize = 16;
+
+/// フェージングによる振幅の変化を求める
+///
+/// 時刻から直接求めるので、プレイヤーを切り替えても時間の流れは途切れない。
+pub struct FadingGenerator {
+    /// 経路毎の (到来角の余弦, 初期位相)
+    paths: Vec<(f64, f64)>,
+}
+
+impl FadingGenerator {
+    pub fn new(seed: u64) -> FadingGenerator {
+        let mut rng = Rng::new(seed);
+        let paths = (0..PATHS)
+            .map(|_| {
+                let angle = 2.0 * PI * rng.uniform();
+                (angle.cos(), 2.0 * PI * rng.uniform())
+            })
+            .collect();
+
+        return FadingGenerator { paths };
+    }
+
+    /// 時刻 `time` [秒]での振幅比
+    ///
+    /// `depth` (0.0 .. 1.0) は弱まる深さ、`period` [秒]は強弱の周期。
+    pub fn gain(&self, fading: Fading, depth: f32, period: f32, time: f64) -> f32 {
+        let depth = depth as f64;
+
+        let gain = match fading {
+            Fading::None => 1.0,
+            // 最大の強さから始まり、半周期で最も弱くなる
+            Fading::Sine => 1.0 - depth * (0.5 - 0.5 * (2.0 * PI * time / period as f64).cos()),
+            // 経路毎のドップラー偏移を周期の逆数とし、合成した振幅は二乗平均が 1 になる。
+            // 受信機の AGC のように、それより強くなった分は頭打ちにする(最大の強さは 1)
+            Fading::Rayleigh => {
+                let (mut re, mut im) = (0.0, 0.0);
+                for (cos, phase) in self.paths.iter() {
+                    let theta = 2.0 * PI * cos * time / period as f64 + phase;
+                    re += theta.cos();
+                    im += theta.sin();
+                }
+                let amplitude = ((re * re + im * im).sqrt() / (PATHS as f64).sqrt()).min(1.0);
+
+                1.0 - depth + depth * amplitude
+            }
+        };
+
+        return gain as f32;
+    }
+}
@@ -0,0 +1,175 @@
+use std::f32::consts::PI;
+
+/// 2次の IIR フィルタ(RBJ Audio EQ Cookbook の係数)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    /// 直前の入力
+    x1: f32,
+    x2: f32,
+    /// 直前の出力
+    y1: f32,
+    y2: f32,
+}
+
+impl Biquad {
+    /// 中心周波数 `center` [Hz]、帯域幅 `bandwidth` [Hz] の帯域通過フィルタ(中心での利得 1)
+    pub fn bandpass(sample_rate: u32, center: f32, bandwidth: f32) -> Biquad {
+        let w0 = 2.0 * PI * center / sample_rate as f32;
+        let alpha = w0.sin() * bandwidth / (2.0 * center);
+        let a0 = 1.0 + alpha;
+
+        return Biquad::new(
+            alpha / a0,
+            0.0,
+            -alpha / a0,
+            -2.0 * w0.cos() / a0,
+            (1.0 - alpha) / a0,
+        );
+    }
+
+    /// 係数を指定して作成する(`a0` は 1 に正規化済み)
+    fn new(b0: f32, b1: f32, b2: f32, a1: f32, a2: f32) -> Biquad {
+        return Biquad {
+            b0,
+            b1,
+            b2,
+            a1,
+            a2,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        };
+    }
+
+    /// 一サンプル分フィルタを通す
+    pub fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2
+            - self.a1 * self.y1
+            - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+
+        return y;
+    }
+
+    /// 白色雑音を通したときの電力比
+    ///
+    /// インパルス応答の二乗和を、十分減衰するまで積算して求める。
+    pub fn noise_gain(&self) -> f32 {
+        let mut filter = self.clone();
+        let mut sum = filter.process(1.0).powi(2);
+        for _ in 0..65536 {
+            sum += filter.process(0.0).powi(2);
+        }
+
+        return sum;
+    }
+}
+
+/// 受信機の CW フィルタの次数(低域通過の原型の次数。帯域通過ではこの倍)
+const ORDER: usize = 4;
+
+/// 複素数の積
+fn mul(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    return (a.0 * b.0 - a.1 * b.1, a.0 * b.1 + a.1 * b.0);
+}
+
+/// 複素数の商
+fn div(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    let d = b.0 * b.0 + b.1 * b.1;
+    return ((a.0 * b.0 + a.1 * b.1) / d, (a.1 * b.0 - a.0 * b.1) / d);
+}
+
+/// 複素数の平方根
+fn sqrt(a: (f64, f64)) -> (f64, f64) {
+    let r = (a.0 * a.0 + a.1 * a.1).sqrt();
+    let re = ((r + a.0) / 2.0).sqrt();
+    let im = ((r - a.0) / 2.0).sqrt();
+    return (re, if a.1 < 0.0 { -im } else { im });
+}
+
+/// 受信機の CW フィルタ
+///
+/// バターワース特性の帯域通過フィルタを 2次のフィルタの縦続で構成する。
+/// 狭くするほど高速の符号でリンギングが目立つ。
+#[derive(Clone, Debug, PartialEq)]
+pub struct CwFilter {
+    /// 中心周波数[Hz]
+    center: f32,
+    /// 帯域幅[Hz]
+    bandwidth: f32,
+    stages: Vec<Biquad>,
+}
+
+impl CwFilter {
+    /// 中心周波数 `center` [Hz]、-3dB 帯域幅 `bandwidth` [Hz] のフィルタ(中心での利得 1)
+    pub fn new(sample_rate: u32, center: f32, bandwidth: f32) -> CwFilter {
+        let fs = sample_rate as f64;
+        let f0 = center as f64;
+        let b = bandwidth as f64;
+
+        // 両端の周波数は中心に対して幾何的に対称とし、双一次変換に合わせて事前に歪ませる
+        let low = (b * b / 4.0 + f0 * f0).sqrt() - b / 2.0;
+        let high = low + b;
+        let warp = |f: f64| 2.0 * fs * (PI as f64 * f / fs).tan();
+        let (wl, wh) = (warp(low), warp(high));
+        let w0 = (wl * wh).sqrt();
+        let bw = wh - wl;
+
+        // 正規化周波数での中心
+        let omega = 2.0 * PI as f64 * f0 / fs;
+        let z1 = (omega.cos(), -omega.sin());
+        let z2 = mul(z1, z1);
+
+        let mut stages = Vec::new();
+        for k in 0..ORDER {
+            // 低域通過の原型の極を帯域通過に変換し、上半面の極から一段ずつ作る
+            let theta = (2 * k + 1) as f64 * PI as f64 / (2 * ORDER) as f64;
+            let p = (-theta.sin() * bw, theta.cos() * bw);
+            let d = sqrt((mul(p, p).0 - 4.0 * w0 * w0, mul(p, p).1));
+            for s in [
+                ((p.0 + d.0) / 2.0, (p.1 + d.1) / 2.0),
+                ((p.0 - d.0) / 2.0, (p.1 - d.1) / 2.0),
+            ] {
+                if s.1 < 0.0 {
+                    continue;
+                }
+                // 双一次変換
+                let z = div((2.0 * fs + s.0, s.1), (2.0 * fs - s.0, -s.1));
+                let a1 = -2.0 * z.0;
+                let a2 = z.0 * z.0 + z.1 * z.1;
+
+                // 零点は直流とナイキスト周波数。中心での利得が 1 になるよう正規化する
+                let num = (1.0 - z2.0, -z2.1);
+                let den = (1.0 + a1 * z1.0 + a2 * z2.0, a1 * z1.1 + a2 * z2.1);
+                let h = div(num, den);
+                let g = 1.0 / (h.0 * h.0 + h.1 * h.1).sqrt();
+
+                stages.push(Biquad::new(g as f32, 0.0, -g as f32, a1 as f32, a2 as f32));
+            }
+        }
+
+        return CwFilter {
+            center,
+            bandwidth,
+            stages,
+        };
+    }
+    /// 中心周波数と帯域幅が同じか
+    pub fn is_same(&self, center: f32, bandwidth: f32) -> bool {
+        return self.center == center && self.bandwidth == bandwidth;
+    }
+
+    /// 一サンプル分フィルタを通す
+    pub fn process(&mut self, x: f32) -> f32 {
+        return self.stages.iter_mut().fold(x, |y, stage| stage.process(y));
+    }
+}
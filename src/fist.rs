@@ -0,0 +1,51 @@
+use crate::random::Rng;
+
+/// 手送りの癖(符号の長さの揺らぎ)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fist {
+    /// 長さの揺らぎ(標準偏差)[%]
+    pub jitter: f64,
+    /// バグキーのように長点だけを重くする量(短点に対して)[%]
+    pub bug: f64,
+}
+
+impl Fist {
+    /// 決まりどおりの短点・長点の長さ `(down, up)` [秒]に癖を加える
+    ///
+    /// バグキーでは短点は機械的に送られるので、揺らぎは長点にのみ加える。
+    pub fn element(
+        &self,
+        is_dah: bool,
+        (down, up): (f64, f64),
+        dit: f64,
+        rng: &mut Rng,
+    ) -> (f64, f64) {
+        if !is_dah && self.bug > 0.0 {
+            return (down, up);
+        }
+
+        let down = if is_dah {
+            down + self.bug / 100.0 * dit
+        } else {
+            down
+        };
+
+        return (self.vary(down, rng), self.vary(up, rng));
+    }
+
+    /// 文字間・語間の時間[秒]
+    pub fn gap(&self, duration: f64, rng: &mut Rng) -> f64 {
+        return self.vary(duration, rng);
+    }
+
+    /// 正規分布に従って長さを揺らす(元の長さの 1/4 が下限)
+    fn vary(&self, duration: f64, rng: &mut Rng) -> f64 {
+        if self.jitter == 0.0 {
+            return duration;
+        }
+
+        let varied = duration * (1.0 + self.jitter / 100.0 * rng.gaussian());
+
+        return varied.max(duration / 4.0);
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+    sync::mpsc::{sync_channel, Receiver, SyncSender},
+    thread::{spawn, JoinHandle},
+};
+
+use anyhow::{anyhow, Result};
+use flacenc::{
+    bitsink::ByteSink,
+    component::{BitRepr, StreamInfo},
+    error::{SourceError, Verify},
+    source::{Context, Fill, FrameBuf, Source},
+};
+
+use crate::{
+    keyer::{Keyer, Mark, Timeline},
+    sink::{Sink, Tags},
+};
+
+/// 一度にエンコーダへ渡すサンプル数
+const CHUNK: usize = 4096;
+
+/// STREAMINFO のメタデータブロック番号
+const STREAMINFO: u8 = 0;
+/// VORBIS_COMMENT のメタデータブロック番号
+const VORBIS_COMMENT: u8 = 4;
+
+/// チャンネル経由で受け取ったサンプルをエンコーダへ渡す
+struct ChannelSource {
+    receiver: Receiver<Vec<i32>>,
+    /// サンプリング周波数
+    sample_rate: u32,
+    /// 受け取り済みで未使用のサンプル
+    pending: Vec<i32>,
+}
+
+impl Source for ChannelSource {
+    fn channels(&self) -> usize {
+        return 1;
+    }
+
+    fn bits_per_sample(&self) -> usize {
+        return 16;
+    }
+
+    fn sample_rate(&self) -> usize {
+        return self.sample_rate as usize;
+    }
+
+    fn read_samples<F: Fill>(
+        &mut self,
+        block_size: usize,
+        dest: &mut F,
+    ) -> Result<usize, SourceError> {
+        // 送り手がいなくなるまでは一ブロック分そろうのを待つ
+        while self.pending.len() < block_size {
+            match self.receiver.recv() {
+                Ok(samples) => self.pending.extend(samples),
+                Err(_) => break,
+            }
+        }
+
+        let len = block_size.min(self.pending.len());
+        dest.fill_interleaved(&self.pending[..len])?;
+        self.pending.drain(..len);
+
+        return Ok(len);
+    }
+}
+
+/// Vorbis comment を FLAC のメタデータの形式にする
+fn vorbis_comment(tags: &Tags) -> Vec<u8> {
+    fn push_string(data: &mut Vec<u8>, s: &str) {
+        data.extend((s.len() as u32).to_le_bytes());
+        data.extend(s.as_bytes());
+    }
+
+    let comments = tags.comments();
+    let mut data = Vec::new();
+
+    push_string(&mut data, "morse-rs");
+    data.extend((comments.len() as u32).to_le_bytes());
+    for (key, value) in comments {
+        push_string(&mut data, &format!("{}={}", key, value));
+    }
+
+    return data;
+}
+
+/// メタデータブロック(ヘッダーと内容)を書き出す
+fn write_metadata<W: Write>(writer: &mut W, typetag: u8, is_last: bool, data: &[u8]) -> Result<()> {
+    let len = (data.len() as u32).to_be_bytes();
+    writer.write_all(&[typetag | if is_last { 0x80 } else { 0x00 }])?;
+    writer.write_all(&len[1..])?;
+    writer.write_all(data)?;
+
+    return Ok(());
+}
+
+/// STREAMINFO の内容
+fn stream_info_data(info: &StreamInfo) -> Result<Vec<u8>> {
+    let mut sink = ByteSink::new();
+    info.write(&mut sink)
+        .map_err(|e| anyhow!("error: FLAC encoding failed: {:?}", e))?;
+
+    return Ok(sink.as_slice().to_vec());
+}
+
+/// 受け取ったサンプルを一ブロックずつエンコードし、フレームをそのままファイルへ書き出す
+///
+/// STREAMINFO (総サンプル数や MD5 など)は長さが変わらないので、最後に先頭へ戻って書き直す。
+fn encode(file: File, mut source: ChannelSource, tags: &Tags) -> Result<()> {
+    let config = flacenc::config::Encoder::default()
+        .into_verified()
+        .map_err(|(_, e)| anyhow!("error: invalid FLAC encoder config: {}", e))?;
+    let block_size = config.block_size;
+    let invalid = |e: flacenc::error::VerifyError| anyhow!("error: invalid FLAC stream: {}", e);
+
+    let mut info = StreamInfo::new(source.sample_rate(), 1, 16).map_err(invalid)?;
+    info.set_block_sizes(block_size, block_size)
+        .map_err(invalid)?;
+
+    let mut writer = BufWriter::new(file);
+    writer.write_all(b"fLaC")?;
+    write_metadata(&mut writer, STREAMINFO, false, &stream_info_data(&info)?)?;
+    write_metadata(&mut writer, VORBIS_COMMENT, true, &vorbis_comment(tags))?;
+
+    let mut buffer = (
+        FrameBuf::with_size(1, block_size).map_err(invalid)?,
+        Context::new(16, 1),
+    );
+    let mut sink = ByteSink::new();
+    while source
+        .read_samples(block_size, &mut buffer)
+        .map_err(|e| anyhow!("error: FLAC encoding failed: {}", e))?
+        > 0
+    {
+        let frame = flacenc::encode_fixed_size_frame(
+            &config,
+            &buffer.0,
+            buffer.1.current_frame_number().unwrap_or(0),
+            &info,
+        )
+        .map_err(|e| anyhow!("error: FLAC encoding failed: {}", e))?;
+        info.update_frame_info(&frame);
+
+        sink.clear();
+        frame
+            .write(&mut sink)
+            .map_err(|e| anyhow!("error: FLAC encoding failed: {:?}", e))?;
+        writer.write_all(sink.as_slice())?;
+    }
+
+    // 最後に一ブロックに満たないフレームがあっても、ブロックの大きさは固定とする
+    info.set_block_sizes(block_size, block_size)
+        .map_err(invalid)?;
+    if info.max_frame_size() == 0 {
+        info.set_frame_sizes(0, 0).map_err(invalid)?;
+    }
+    info.set_md5_digest(&buffer.1.md5_digest());
+    info.set_total_samples(buffer.1.total_samples());
+
+    writer.seek(SeekFrom::Start(4))?;
+    write_metadata(&mut writer, STREAMINFO, false, &stream_info_data(&info)?)?;
+    writer.flush()?;
+
+    return Ok(());
+}
+
+/// モールス音を FLAC ファイル(16bit モノラル)に書き出す
+///
+/// エンコードは別スレッドで行い、フレーム毎にファイルへ書き出すので、長い電文でもメモリに溜めない。
+pub struct FlacRenderer {
+    /// サンプリング周波数
+    sample_rate: u32,
+    /// タイムラインから波形を生成
+    keyer: Keyer,
+    /// エンコーダへサンプルを送る
+    sender: Option<SyncSender<Vec<i32>>>,
+    /// エンコードを行うスレッド
+    encoder: Option<JoinHandle<Result<()>>>,
+}
+
+impl FlacRenderer {
+    /// 出力ファイルを作成する
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32, tags: Tags) -> Result<FlacRenderer> {
+        let file = File::create(path.as_ref()).map_err(|e| {
+            anyhow!(
+                "error: cannot create output file ({}): {}",
+                path.as_ref().display(),
+                e
+            )
+        })?;
+
+        let (sender, receiver) = sync_channel(16);
+        let source = ChannelSource {
+            receiver,
+            sample_rate,
+            pending: Vec::new(),
+        };
+        let encoder = spawn(move || encode(file, source, &tags));
+
+        return Ok(FlacRenderer {
+            sample_rate,
+            keyer: Keyer::new(sample_rate),
+            sender: Some(sender),
+            encoder: Some(encoder),
+        });
+    }
+
+    /// サンプルをエンコーダへ送る
+    fn flush(&mut self, samples: &mut Vec<i32>) -> Result<()> {
+        if let Some(ref sender) = self.sender {
+            if sender.send(std::mem::take(samples)).is_err() {
+                // エンコーダが止まっていれば、その理由を返す
+                return self.join();
+            }
+        }
+
+        return Ok(());
+    }
+
+    /// 確定したところまで波形にしてエンコーダへ送る。`all` なら最後まで
+    fn render(&mut self, all: bool, on_mark: &mut dyn FnMut(&Mark)) -> Result<()> {
+        let mut marks = Vec::new();
+        let mut samples = Vec::with_capacity(CHUNK);
+
+        while let Some(value) = self.keyer.next_sample_within(all, &mut marks) {
+            // 16bit の範囲に収める(WAV と同じく、i16 への変換で飽和させる)
+            samples.push((value * i16::MAX as f32) as i16 as i32);
+            if samples.len() == CHUNK {
+                self.flush(&mut samples)?;
+            }
+        }
+        self.flush(&mut samples)?;
+        marks.iter().for_each(on_mark);
+
+        return Ok(());
+    }
+
+    /// エンコードの終了を待つ
+    fn join(&mut self) -> Result<()> {
+        self.sender = None;
+
+        return match self.encoder.take() {
+            Some(encoder) => encoder
+                .join()
+                .map_err(|_| anyhow!("error: FLAC encoder panicked"))?,
+            None => Err(anyhow!("error: FLAC encoder already finished")),
+        };
+    }
+}
+
+impl Sink for FlacRenderer {
+    fn sample_rate(&self) -> u32 {
+        return self.sample_rate;
+    }
+
+    /// タイムラインを波形にしてエンコーダへ送る
+    fn send(&mut self, timeline: Timeline, on_mark: &mut dyn FnMut(&Mark)) -> Result<()> {
+        self.keyer.push(timeline);
+
+        return self.render(false, on_mark);
+    }
+
+    /// 残りをエンコードして完了する
+    fn finish(&mut self, on_mark: &mut dyn FnMut(&Mark)) -> Result<()> {
+        self.render(true, on_mark)?;
+
+        return self.join();
+    }
+}
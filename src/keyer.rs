@@ -0,0 +1,478 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    f32::consts::PI,
+};
+
+use clap::ValueEnum;
+
+use crate::{
+    fading::{Fading, FadingGenerator},
+    filter::CwFilter,
+    noise::{noise_level, NoiseGenerator, NoiseKind},
+    oscillator::{Oscillator, Waveform},
+};
+
+/// 発音の立ち上がり・立ち下がりの形
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Envelope {
+    /// レイズドコサイン
+    #[default]
+    Cosine,
+    /// ブラックマン窓
+    Blackman,
+}
+
+impl Envelope {
+    /// 立ち上がりの途中 `x` (0.0 .. 1.0) での振幅比
+    fn gain(&self, x: f32) -> f32 {
+        match self {
+            Envelope::Cosine => 0.5 - 0.5 * (PI * x).cos(),
+            Envelope::Blackman => 0.42 - 0.5 * (PI * x).cos() + 0.08 * (2.0 * PI * x).cos(),
+        }
+    }
+}
+
+/// 発音の音色
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tone {
+    /// 周波数
+    pub frequency: f32,
+    /// 音量
+    pub volume: f32,
+    /// 音量換算値
+    pub power: f32,
+    /// 立ち上がり・立ち下がりの時間[ms]
+    pub rise_time: f32,
+    /// 立ち上がり・立ち下がりの形
+    pub envelope: Envelope,
+    /// 波形
+    pub waveform: Waveform,
+    /// 第2倍音以降の振幅比
+    pub harmonics: Vec<f32>,
+    /// キーを押した瞬間の周波数のずれ[Hz](チャープ)
+    pub chirp: f32,
+    /// 一行の間に周波数がずれていく量[Hz](ドリフト)
+    pub drift: f32,
+    /// 電源のリプルによる振幅の変動の深さ(0.0 .. 1.0)
+    pub hum: f32,
+    /// 電源のリプルの周波数[Hz]
+    pub hum_frequency: f32,
+}
+
+/// チャープが収まるまでの時定数[秒]
+const CHIRP_TIME: f32 = 0.01;
+
+/// 受信状態(信号に重なる雑音など)
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Condition {
+    /// 背景雑音の種類
+    pub noise: NoiseKind,
+    /// 背景雑音に対する信号の強さ[dB]。`None` なら雑音なし
+    pub snr: Option<f32>,
+    /// フェージング(QSB)の種類
+    pub qsb: Fading,
+    /// フェージングで弱まる深さ(0.0 .. 1.0)
+    pub qsb_depth: f32,
+    /// フェージングの周期[秒]
+    pub qsb_period: f32,
+    /// 受信機の CW フィルタの帯域幅[Hz]。`None` ならフィルタなし
+    pub filter: Option<f32>,
+    /// 受信機の CW フィルタの中心周波数[Hz]
+    pub filter_center: f32,
+    /// 雑音などの乱数のシード
+    pub seed: u64,
+}
+
+/// 符号の構成要素(キーを押している/離している区間)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Element {
+    /// キーを押している(発音する)なら true
+    pub key_down: bool,
+    /// 長さ[サンプル]
+    pub samples: u64,
+}
+
+/// 発音中の位置を知らせる目印
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mark {
+    /// タイムラインの開始(先頭の間隔を含む)
+    Start,
+    /// 行の発音開始
+    Line(String),
+    /// 文字の発音開始
+    Char(char),
+    /// 行の発音終了
+    End,
+}
+
+/// 一行分の符号をサンプル単位で並べたもの
+///
+/// 要素の長さは秒で積算し、サンプル数へは積算値から換算するので、
+/// 丸め誤差が行内で蓄積しない。
+#[derive(Clone, Debug)]
+pub struct Timeline {
+    /// サンプリング周波数
+    sample_rate: u32,
+    /// 音色
+    tone: Tone,
+    /// 受信状態
+    condition: Condition,
+    /// 音色に応じた波形の生成
+    oscillator: Oscillator,
+    /// 音量換算値
+    vol: f32,
+    /// 立ち上がり・立ち下がりの長さ[サンプル]
+    rise: f32,
+    /// 積算した長さ[秒]
+    elapsed: f64,
+    /// 積算した長さ[サンプル]
+    position: u64,
+    /// キーイングの並び
+    elements: Vec<Element>,
+    /// 目印とその位置[サンプル]
+    marks: Vec<(u64, Mark)>,
+    /// 前のタイムラインの開始からの開始位置[サンプル]。`None` なら前のものが終わってから
+    offset: Option<u64>,
+}
+
+impl Timeline {
+    pub fn new(sample_rate: u32, tone: Tone, condition: Condition) -> Timeline {
+        return Timeline {
+            sample_rate,
+            condition,
+            oscillator: Oscillator::new(
+                tone.waveform,
+                &tone.harmonics,
+                tone.frequency,
+                sample_rate,
+            ),
+            // 数値そのままだと、音量の変化が不自然(小音で急激に、大音で微小)なので、オーディオ用のＣカーブ特性のボリュームを再現。デフォルトの数値は感覚で決定
+            vol: tone.volume.powf(tone.power),
+            rise: tone.rise_time * sample_rate as f32 / 1000.0,
+            tone,
+            elapsed: 0.0,
+            position: 0,
+            elements: Vec::new(),
+            marks: Vec::new(),
+            offset: None,
+        };
+    }
+
+    /// 前のタイムラインの開始から `offset` [秒]後に、重ねて発音を始める
+    pub fn set_offset(&mut self, offset: f64) {
+        self.offset = Some((offset * self.sample_rate as f64).round() as u64);
+    }
+
+    /// 前のタイムラインの開始からの開始位置[サンプル]
+    pub fn offset(&self) -> Option<u64> {
+        return self.offset;
+    }
+
+    /// 指定時間[秒]のキーイングを追加する
+    pub fn push(&mut self, key_down: bool, duration: f64) {
+        self.elapsed += duration;
+        let end = (self.elapsed * self.sample_rate as f64).round() as u64;
+        let samples = end - self.position;
+        self.position = end;
+
+        // 同じ状態が続くなら一つにまとめる
+        if let Some(last) = self.elements.last_mut() {
+            if last.key_down == key_down {
+                last.samples += samples;
+                return;
+            }
+        }
+        self.elements.push(Element { key_down, samples });
+    }
+
+    /// 現在位置に目印を付ける
+    pub fn mark(&mut self, mark: Mark) {
+        self.marks.push((self.position, mark));
+    }
+
+    /// 音色
+    pub fn tone(&self) -> &Tone {
+        return &self.tone;
+    }
+
+    /// 受信状態
+    pub fn condition(&self) -> &Condition {
+        return &self.condition;
+    }
+
+    /// キーイングの並び
+    pub fn elements(&self) -> &[Element] {
+        return &self.elements;
+    }
+
+    /// 目印とその位置[サンプル]
+    pub fn marks(&self) -> &[(u64, Mark)] {
+        return &self.marks;
+    }
+
+    /// 全体の長さ[サンプル]
+    pub fn len(&self) -> u64 {
+        return self.position;
+    }
+
+    /// 長さが 0 か
+    pub fn is_empty(&self) -> bool {
+        return self.position == 0;
+    }
+}
+
+/// 発音中のタイムラインと、その中の位置
+struct Cursor {
+    timeline: Timeline,
+    /// 発音中の要素
+    element: usize,
+    /// 発音中の要素の残り[サンプル]
+    remaining: u64,
+    /// タイムライン先頭からの位置[サンプル]
+    position: u64,
+    /// 次に知らせる目印
+    mark: usize,
+    /// 波形の位相(0.0 .. 1.0)
+    phase: f32,
+}
+
+impl Cursor {
+    fn new(timeline: Timeline) -> Cursor {
+        return Cursor {
+            remaining: timeline.elements.first().map_or(0, |e| e.samples),
+            timeline,
+            element: 0,
+            position: 0,
+            mark: 0,
+            phase: 0.0,
+        };
+    }
+
+    /// 現在位置までの目印を知らせ、長さ 0 の要素を読み飛ばす
+    ///
+    /// タイムラインの終端に達したら、残りの目印と `Mark::End` を知らせて false を返す。
+    fn advance(&mut self, marks: &mut Vec<Mark>) -> bool {
+        while let Some((position, mark)) = self.timeline.marks.get(self.mark) {
+            if *position > self.position {
+                break;
+            }
+            marks.push(mark.clone());
+            self.mark += 1;
+        }
+
+        while self.remaining == 0 && self.element < self.timeline.elements.len() {
+            self.element += 1;
+            self.remaining = self
+                .timeline
+                .elements
+                .get(self.element)
+                .map_or(0, |e| e.samples);
+        }
+
+        if self.element < self.timeline.elements.len() {
+            return true;
+        }
+
+        marks.extend(
+            self.timeline.marks[self.mark..]
+                .iter()
+                .map(|(_, m)| m.clone()),
+        );
+        marks.push(Mark::End);
+
+        return false;
+    }
+
+    /// 現在位置の信号を生成し、一サンプル進める
+    fn next_sample(&mut self, sample_rate: u32) -> f32 {
+        let timeline = &self.timeline;
+        let element = timeline.elements[self.element];
+        let value = if element.key_down {
+            // 要素の端からの距離で立ち上がり・立ち下がりを決める(要素の半分が上限)
+            let rise = timeline.rise.min(element.samples as f32 / 2.0);
+            let edge = (element.samples - self.remaining).min(self.remaining - 1) as f32 + 0.5;
+            let gain = if edge < rise {
+                timeline.tone.envelope.gain(edge / rise)
+            } else {
+                1.0
+            };
+
+            // 電源のリプルで振幅が揺れる
+            let time = self.position as f32 / sample_rate as f32;
+            let tone = &timeline.tone;
+            let hum = 1.0 - tone.hum * (0.5 - 0.5 * (2.0 * PI * tone.hum_frequency * time).cos());
+
+            timeline.oscillator.value(self.phase) * timeline.vol * gain * hum
+        } else {
+            0.0
+        };
+
+        // 周波数は、行の終わりに向けてドリフトし、キーを押した直後はチャープでずれる
+        let tone = &timeline.tone;
+        let mut frequency = tone.frequency;
+        if tone.drift != 0.0 {
+            frequency += tone.drift * self.position as f32 / timeline.position.max(1) as f32;
+        }
+        if tone.chirp != 0.0 && element.key_down {
+            let elapsed = (element.samples - self.remaining) as f32 / sample_rate as f32;
+            frequency += tone.chirp * (-elapsed / CHIRP_TIME).exp();
+        }
+
+        self.phase = (self.phase + frequency / sample_rate as f32).fract();
+        self.remaining -= 1;
+        self.position += 1;
+
+        return value;
+    }
+}
+
+/// タイムラインに従ってキーイングしながら発音の波形を生成する
+///
+/// オーディオのコールバックから直接呼び出せるよう、一サンプルずつ生成する。
+/// キーイングの前後は、クリックを防ぐためにタイムラインの音色に従って整形する。
+/// 開始位置が指定されたタイムラインは、前のタイムラインと重ねて発音し、混ぜ合わせる。
+/// 信号にはフェージングをかけ、背景雑音は、キーを離している間も途切れずに重ねる。
+/// 混ぜ合わせた後、受信機の CW フィルタを通す。
+pub struct Keyer {
+    /// サンプリング周波数
+    sample_rate: u32,
+    /// 発音待ちのタイムラインと、その開始時刻[サンプル]
+    queue: VecDeque<(u64, Timeline)>,
+    /// 発音中のタイムライン
+    voices: Vec<Cursor>,
+    /// 最後に追加したタイムラインの開始時刻[サンプル]
+    last_start: u64,
+    /// 追加したタイムラインがすべて終わる時刻[サンプル]
+    end: u64,
+    /// 背景雑音の基準(最後に発音を始めたタイムラインの受信状態と音量換算値)
+    background: Option<(Condition, f32)>,
+    /// 生成したサンプル数(現在の時刻)
+    time: u64,
+    /// 背景雑音(最初に雑音を指定したタイムラインのシードで作成)
+    noise: Option<NoiseGenerator>,
+    /// シード毎のフェージング
+    fading: HashMap<u64, FadingGenerator>,
+    /// 受信機の CW フィルタ(最後に発音を始めたタイムラインの受信状態に従う)
+    filter: Option<CwFilter>,
+}
+
+impl Keyer {
+    pub fn new(sample_rate: u32) -> Keyer {
+        return Keyer {
+            sample_rate,
+            queue: VecDeque::new(),
+            voices: Vec::new(),
+            last_start: 0,
+            end: 0,
+            background: None,
+            time: 0,
+            noise: None,
+            fading: HashMap::new(),
+            filter: None,
+        };
+    }
+
+    /// タイムラインを発音待ちに追加する
+    ///
+    /// 開始位置の指定がなければ、それまでに追加したものがすべて終わってから開始する。
+    /// 発音が途切れていれば、すぐに開始する。
+    pub fn push(&mut self, timeline: Timeline) {
+        let start = match timeline.offset {
+            Some(offset) => self.last_start + offset,
+            None => self.end,
+        }
+        .max(self.time);
+
+        self.last_start = start;
+        self.end = self.end.max(start + timeline.len());
+        self.queue.push_back((start, timeline));
+    }
+
+    /// 開始時刻に達したタイムラインを発音中にする
+    fn start(&mut self, marks: &mut Vec<Mark>) {
+        while self
+            .queue
+            .front()
+            .is_some_and(|(start, _)| *start <= self.time)
+        {
+            let (_, timeline) = self.queue.pop_front().unwrap();
+            marks.push(Mark::Start);
+            self.background = Some((timeline.condition, timeline.vol));
+            self.voices.push(Cursor::new(timeline));
+        }
+    }
+
+    /// 次のサンプルを、`all` でなければ最後に追加したタイムラインの開始時刻まで生成する
+    ///
+    /// 後から重ねて追加されるタイムラインは、それより前には始まらないので、
+    /// ファイルへの書き出しでは、ここまでを先に確定できる。達したら `None` を返す。
+    pub fn next_sample_within(&mut self, all: bool, marks: &mut Vec<Mark>) -> Option<f32> {
+        if !all && self.last_start <= self.time {
+            return None;
+        }
+
+        return self.next_sample(marks);
+    }
+
+    /// 次のサンプルを生成する
+    ///
+    /// 通過した目印は `marks` に追加する。発音するものがなければ `None` を返す。
+    pub fn next_sample(&mut self, marks: &mut Vec<Mark>) -> Option<f32> {
+        self.start(marks);
+        self.voices.retain_mut(|cursor| cursor.advance(marks));
+
+        if self.voices.is_empty() && self.queue.is_empty() {
+            return None;
+        }
+
+        let sample_rate = self.sample_rate;
+        let time = self.time as f64 / sample_rate as f64;
+        let mut value = 0.0;
+
+        for cursor in self.voices.iter_mut() {
+            let condition = cursor.timeline.condition;
+            let mut signal = cursor.next_sample(sample_rate);
+            if condition.qsb != Fading::None {
+                let fading = self
+                    .fading
+                    .entry(condition.seed)
+                    .or_insert_with(|| FadingGenerator::new(condition.seed));
+                signal *= fading.gain(
+                    condition.qsb,
+                    condition.qsb_depth,
+                    condition.qsb_period,
+                    time,
+                );
+            }
+            value += signal;
+        }
+
+        if let Some((condition, vol)) = self.background {
+            if let Some(snr) = condition.snr {
+                let noise = self
+                    .noise
+                    .get_or_insert_with(|| NoiseGenerator::new(sample_rate, condition.seed));
+                value += noise.next(condition.noise) * noise_level(snr) * vol;
+            }
+
+            match condition.filter {
+                Some(bandwidth) => {
+                    let center = condition.filter_center;
+                    if !self
+                        .filter
+                        .as_ref()
+                        .is_some_and(|f| f.is_same(center, bandwidth))
+                    {
+                        self.filter = Some(CwFilter::new(sample_rate, center, bandwidth));
+                    }
+                    value = self.filter.as_mut().unwrap().process(value);
+                }
+                None => self.filter = None,
+            }
+        }
+
+        self.time += 1;
+
+        return Some(value);
+    }
+}
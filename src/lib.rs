@@ -1,3 +1,23 @@
 pub mod args;
+pub mod decoder;
+pub mod detector;
+pub mod encoder;
+pub mod event;
+pub mod fading;
+pub mod filter;
+pub mod fist;
+pub mod flac;
+pub mod keyer;
 pub mod morse;
+pub mod noise;
+pub mod ogg;
+pub mod oscillator;
+pub mod player;
+pub mod random;
+pub mod raw;
+pub mod sink;
+pub mod skimmer;
+pub mod stream;
+pub mod timing;
 pub mod translation_table;
+pub mod wav;
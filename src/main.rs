@@ -1,10 +1,21 @@
 use std::{
     fs::File,
-    io::{stdin, BufReader},
+    io::{stdin, stdout, BufRead, BufReader, Write},
 };
 
-use anyhow::Result;
-use morse_rs::{args::get_args, morse::Morse};
+use anyhow::{anyhow, Result};
+use morse_rs::{
+    args::{get_args, Args, Command, DecodeArgs, EncodeArgs, SkimArgs},
+    decoder::{Decoded, Decoder},
+    detector::{find_tone, ToneDetector, BLOCK_TIME},
+    encoder::{Encoder, Symbols},
+    morse::Morse,
+    raw::{RawSink, RawSource},
+    sink::list_devices,
+    skimmer::skim,
+    stream::{Heard, StreamDecoder},
+    wav::read_wav,
+};
 use stringreader::StringReader;
 
 /// Intra-character space <br>
@@ -22,6 +33,18 @@ use stringreader::StringReader;
 ///       + 7*Σ{単語間ギャップ}=7*1=7
 ///      -> 10+12+9+12+7=50//
 /// 短点[ms]  = 60 * 1000 / (50 * wpm);
+///
+/// CODEX = 60短点(`--standard codex`)
+///   内訳
+///     "CODEX" -> "-.-. --- -.. . -..-"
+///      -> 1*Σ.=7
+///       + 3*Σ-=3*8=24
+///       + 1*Σ{文字内ギャップ}=15-5=10
+///       + 3*Σ{文字間ギャップ}=3*4=12
+///       + 7*Σ{単語間ギャップ}=7*1=7
+///      -> 7+24+10+12+7=60//
+/// 短点[ms]  = 60 * 1000 / (60 * wpm);
+/// wpm は小数でも指定でき(22.5 など)、長さは秒単位の実数で計算する。
 
 /// (JARLの「モールス電信技能認定」)[https://www.jarl.org/Japanese/1_Tanoshimo/1-4_Morse/Morse.htm]での速度
 /// | 段位 | CPM| WPM | 短点[ms] | 長点[ms] |
@@ -34,7 +57,8 @@ use stringreader::StringReader;
 /// | 4段  |140 |  28 |  42.86  | 128.57   |
 /// | 5段  |160 |  32 |  37.50  | 112.50   |
 /// |名人位|180 |  36 |  33.33  | 100.00   |
-/// モールス通信 和文 CPMの７-８割
+/// `--cpm` で CPM を、`--level 初段` のように段位を指定できる。
+/// モールス通信 和文 CPMの７-８割(`--wabun-ratio`、既定 75%)
 ///             欧文普通語 CPMとほぼ同じ
 ///             欧文暗語 CPMの８-９割
 /// 600Hz ... 55.555 回 per 33.33ms
@@ -43,21 +67,206 @@ use stringreader::StringReader;
 fn main() -> Result<()> {
     let opt = get_args()?;
 
+    if opt.list_devices {
+        return list_devices();
+    }
+
+    match opt.command {
+        Some(Command::Decode(ref args)) => return decode(args),
+        Some(Command::Skim(ref args)) => return skim_recording(args),
+        Some(Command::Encode(ref args)) => return encode(&opt, args),
+        None => {}
+    }
+
     let mut morse = Morse::new(&opt);
 
     if let Some(ref text) = opt.text {
         // コマンドラインに電文を記述
         let reader = StringReader::new(text);
         let mut bufreader = BufReader::new(reader);
-        morse.play(&mut bufreader)?;
+        perform(&mut morse, &opt, &mut bufreader)?;
     } else if let Some(ref input) = opt.input {
         // 電文ファイルを指定
         let mut reader = BufReader::new(File::open(input.to_str().unwrap()).unwrap());
-        morse.play(&mut reader)?;
+        perform(&mut morse, &opt, &mut reader)?;
     } else {
         // 標準入力から電文を取得
         let mut reader = BufReader::new(stdin());
-        morse.play(&mut reader)?;
+        perform(&mut morse, &opt, &mut reader)?;
+    }
+
+    return Ok(());
+}
+
+/// 出力先の指定に応じて、発音または WAV ファイル・標準出力へ書き出す
+fn perform<R: BufRead>(morse: &mut Morse, opt: &Args, reader: &mut R) -> Result<()> {
+    if let Some(ref output) = opt.output {
+        return morse.render(reader, output, opt.sample_rate);
+    }
+    if let Some(format) = opt.raw {
+        let mut sink = RawSink::new(stdout().lock(), format, opt.sample_rate);
+        return morse.perform(reader, &mut sink);
+    }
+
+    return morse.play(reader);
+}
+
+/// 短点・長点の表記を一行ずつ復号して出力する
+///
+/// 複数の文字に当たる符号や、どの文字にも当たらない符号は標準エラーに知らせる。
+fn decode(args: &DecodeArgs) -> Result<()> {
+    let decoder = Decoder::new(args.alphabet, &args.letter_separator, &args.word_separator);
+
+    if let Some(ref path) = args.wav {
+        // 録音の音を聞き取る
+        let (samples, sample_rate) = read_wav(path)?;
+        let tone = args
+            .tone
+            .or_else(|| find_tone(&samples, sample_rate))
+            .ok_or_else(|| anyhow!("error: no tone is found in the recording."))?;
+        let keying = ToneDetector::new(tone, sample_rate, BLOCK_TIME).keying(&samples);
+        report(&decoder.decode_keying(&keying));
+
+        return Ok(());
+    }
+    if args.stdin {
+        return listen(args, decoder);
+    }
+
+    let reader: Box<dyn BufRead> = match args.code {
+        Some(ref code) => Box::new(BufReader::new(StringReader::new(code))),
+        None => Box::new(BufReader::new(stdin())),
+    };
+
+    for line in reader.lines() {
+        report(&decoder.decode(&line?));
+    }
+
+    return Ok(());
+}
+
+/// 速度の表示を更新する変化[wpm]
+const WPM_STEP: f64 = 2.0;
+
+/// 標準入力の生 PCM を受信しながら復号し、文字が確定する度に出力する
+///
+/// 送信の始めと、語の区切りで速度が変わったときに、推定した速度を `[20 wpm]` のように出力する。
+/// 速度をまだ推定できていない間は出力しない。
+fn listen(args: &DecodeArgs, decoder: Decoder) -> Result<()> {
+    let mut stream = StreamDecoder::new(decoder, args.sample_rate, args.tone);
+    let mut out = stdout().lock();
+    let mut is_line_start = true;
+    // 最後に表示した速度
+    let mut shown: Option<f64> = None;
+
+    let mut show = |heard: Heard, wpm: Option<f64>| -> Result<()> {
+        match heard {
+            Heard::Text(text) => {
+                // 速度をまだ推定できていなければ、推定できてから表示する
+                if let Some(wpm) = wpm.map(f64::round) {
+                    if is_line_start {
+                        write!(out, "[{} wpm] ", wpm)?;
+                        shown = Some(wpm);
+                    } else if text.starts_with(' ')
+                        && shown.is_none_or(|s| WPM_STEP <= (wpm - s).abs())
+                    {
+                        write!(out, " [{} wpm]", wpm)?;
+                        shown = Some(wpm);
+                    }
+                }
+                write!(out, "{}", text)?;
+                is_line_start = false;
+            }
+            Heard::Break => {
+                writeln!(out)?;
+                is_line_start = true;
+            }
+        }
+        out.flush()?;
+
+        return Ok(());
+    };
+
+    for x in RawSource::new(stdin().lock(), args.format) {
+        for heard in stream.push(x?) {
+            show(heard, stream.wpm())?;
+        }
+    }
+    for heard in stream.finish() {
+        show(heard, stream.wpm())?;
+    }
+
+    return Ok(());
+}
+
+/// 復号した文字列を出力し、判定できなかった符号を標準エラーに知らせる
+fn report(decoded: &Decoded) {
+    println!("{}", decoded.text);
+
+    for (code, candidates) in &decoded.ambiguous {
+        let candidates: Vec<String> = candidates.iter().map(|c| c.to_string()).collect();
+        eprintln!(
+            "Warning: `{}` is ambiguous ({}).",
+            code,
+            candidates.join(" ")
+        );
+    }
+    for code in &decoded.unknown {
+        eprintln!("Warning: `{}` is unknown.", code);
+    }
+}
+
+/// 録音の中の信号をすべて復号し、送信毎に開始時刻と周波数を付けて出力する
+fn skim_recording(args: &SkimArgs) -> Result<()> {
+    let decoder = Decoder::new(args.alphabet, " ", "/");
+    let (samples, sample_rate) = read_wav(&args.wav)?;
+
+    for t in skim(&samples, sample_rate, args.low, args.high, &decoder) {
+        let minutes = (t.start / 60.0).floor();
+        println!(
+            "{:02}:{:04.1} {:>6.0} Hz  {}",
+            minutes,
+            t.start - 60.0 * minutes,
+            t.frequency,
+            t.decoded.text
+        );
+    }
+
+    return Ok(());
+}
+
+/// 電文を発音せずに、一行ずつ短点・長点の表記にして出力する
+///
+/// 速度や `#!` 行の解釈は発音と同じ。出力デバイスは使わない。
+fn encode(opt: &Args, args: &EncodeArgs) -> Result<()> {
+    let symbols = if args.phonetic {
+        Symbols::Phonetic
+    } else {
+        Symbols::Marks {
+            dot: args.dot.clone(),
+            dash: args.dash.clone(),
+        }
+    };
+    let encoder = Encoder::new(symbols, &args.letter_separator, &args.word_separator);
+    let morse = Morse::new(opt);
+
+    let reader: Box<dyn BufRead> = if let Some(ref text) = args.text {
+        Box::new(BufReader::new(StringReader::new(text)))
+    } else if let Some(ref input) = args.input {
+        Box::new(BufReader::new(File::open(input)?))
+    } else {
+        Box::new(BufReader::new(stdin()))
+    };
+
+    let mut out = stdout().lock();
+    for encoded in encoder.encode(morse.events(reader)) {
+        let encoded = encoded?;
+        if args.json {
+            writeln!(out, "{}", encoded.to_json())?;
+        } else {
+            writeln!(out, "{}", encoded.code)?;
+        }
+        out.flush()?;
     }
 
     return Ok(());
@@ -0,0 +1,81 @@
+use clap::ValueEnum;
+
+use crate::{filter::Biquad, random::Rng};
+
+/// 背景雑音の種類
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum NoiseKind {
+    /// 白色雑音
+    #[default]
+    White,
+    /// ピンクノイズ(1/f)
+    Pink,
+    /// 受信機の音声帯域(300 .. 3000 Hz)に制限した雑音
+    Band,
+}
+
+/// 帯域制限した雑音の下端[Hz]
+const BAND_LOW: f32 = 300.0;
+
+/// 帯域制限した雑音の上端[Hz]
+const BAND_HIGH: f32 = 3000.0;
+
+/// ピンクノイズ・フィルタの出力の標準偏差(入力が標準正規分布の場合)
+const PINK_RMS: f32 = 3.066;
+
+/// SN 比[dB]から、信号の振幅 1 に対する雑音の標準偏差を求める
+///
+/// 信号の電力は、同じ振幅の正弦波(振幅の二乗の半分)とする。
+pub fn noise_level(snr: f32) -> f32 {
+    return (0.5 / 10f32.powf(snr / 10.0)).sqrt();
+}
+
+/// 背景雑音を一サンプルずつ生成する(標準偏差 1)
+pub struct NoiseGenerator {
+    rng: Rng,
+    /// ピンクノイズ・フィルタ(Paul Kellet)の状態
+    pink: [f32; 7],
+    /// 帯域制限用のフィルタ
+    band: Biquad,
+    /// 帯域制限で減る電力を補う係数
+    band_gain: f32,
+}
+
+impl NoiseGenerator {
+    pub fn new(sample_rate: u32, seed: u64) -> NoiseGenerator {
+        // 低いサンプリング周波数では、上端をナイキスト周波数より下に抑える
+        let high = BAND_HIGH.min(sample_rate as f32 * 0.45);
+        let band = Biquad::bandpass(sample_rate, (BAND_LOW * high).sqrt(), high - BAND_LOW);
+        let band_gain = 1.0 / band.noise_gain().sqrt();
+
+        return NoiseGenerator {
+            rng: Rng::new(seed),
+            pink: [0.0; 7],
+            band,
+            band_gain,
+        };
+    }
+
+    /// 次のサンプル
+    pub fn next(&mut self, kind: NoiseKind) -> f32 {
+        let white = self.rng.gaussian() as f32;
+
+        return match kind {
+            NoiseKind::White => white,
+            NoiseKind::Pink => {
+                let b = &mut self.pink;
+                b[0] = 0.99886 * b[0] + white * 0.0555179;
+                b[1] = 0.99332 * b[1] + white * 0.0750759;
+                b[2] = 0.96900 * b[2] + white * 0.153852;
+                b[3] = 0.86650 * b[3] + white * 0.3104856;
+                b[4] = 0.55000 * b[4] + white * 0.5329522;
+                b[5] = -0.7616 * b[5] - white * 0.0168980;
+                let pink = b.iter().sum::<f32>() + white * 0.5362;
+                b[6] = white * 0.115926;
+
+                pink / PINK_RMS
+            }
+            NoiseKind::Band => self.band.process(white) * self.band_gain,
+        };
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    fs::File,
+    io::BufWriter,
+    num::{NonZeroU32, NonZeroU8},
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use vorbis_rs::{VorbisEncoder, VorbisEncoderBuilder};
+
+use crate::{
+    keyer::{Keyer, Mark, Timeline},
+    sink::{Sink, Tags},
+};
+
+/// 一度にエンコーダへ渡すサンプル数
+const CHUNK: usize = 1024;
+
+/// モールス音を Ogg Vorbis ファイル(モノラル)に書き出す
+pub struct OggRenderer {
+    /// エンコーダ(出力完了時に取り出す)
+    encoder: Option<VorbisEncoder<BufWriter<File>>>,
+    /// サンプリング周波数
+    sample_rate: u32,
+    /// タイムラインから波形を生成
+    keyer: Keyer,
+}
+
+impl OggRenderer {
+    /// 出力ファイルを作成し、タグを書き込む
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32, tags: Tags) -> Result<OggRenderer> {
+        let file = File::create(path.as_ref()).map_err(|e| {
+            anyhow!(
+                "error: cannot create output file ({}): {}",
+                path.as_ref().display(),
+                e
+            )
+        })?;
+
+        let mut builder = VorbisEncoderBuilder::new(
+            NonZeroU32::new(sample_rate).ok_or(anyhow!("error: invalid sample rate"))?,
+            NonZeroU8::MIN,
+            BufWriter::new(file),
+        )?;
+        builder.comment_tags(tags.comments())?;
+
+        return Ok(OggRenderer {
+            encoder: Some(builder.build()?),
+            sample_rate,
+            keyer: Keyer::new(sample_rate),
+        });
+    }
+
+    /// 確定したところまで波形にしてエンコードする。`all` なら最後まで
+    fn render(&mut self, all: bool, on_mark: &mut dyn FnMut(&Mark)) -> Result<()> {
+        let encoder = self
+            .encoder
+            .as_mut()
+            .ok_or(anyhow!("error: Ogg encoder already finished"))?;
+        let mut marks = Vec::new();
+        let mut samples = Vec::with_capacity(CHUNK);
+
+        while let Some(value) = self.keyer.next_sample_within(all, &mut marks) {
+            samples.push(value);
+            if samples.len() == CHUNK {
+                encoder.encode_audio_block([&samples])?;
+                samples.clear();
+            }
+        }
+        if !samples.is_empty() {
+            encoder.encode_audio_block([&samples])?;
+        }
+        marks.iter().for_each(on_mark);
+
+        return Ok(());
+    }
+}
+
+impl Sink for OggRenderer {
+    fn sample_rate(&self) -> u32 {
+        return self.sample_rate;
+    }
+
+    /// タイムラインを波形にしてエンコードする
+    fn send(&mut self, timeline: Timeline, on_mark: &mut dyn FnMut(&Mark)) -> Result<()> {
+        self.keyer.push(timeline);
+
+        return self.render(false, on_mark);
+    }
+
+    /// 残りをエンコードして完了する
+    fn finish(&mut self, on_mark: &mut dyn FnMut(&Mark)) -> Result<()> {
+        self.render(true, on_mark)?;
+        if let Some(encoder) = self.encoder.take() {
+            encoder.finish()?;
+        }
+
+        return Ok(());
+    }
+}
@@ -0,0 +1,133 @@
+use std::f32::consts::PI;
+
+use clap::ValueEnum;
+
+/// 発音の波形
+///
+/// `-bl` の付くものは、ナイキスト周波数未満の倍音のみで合成した帯域制限版。
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Waveform {
+    /// 正弦波
+    #[default]
+    Sine,
+    /// 矩形波
+    Square,
+    /// 三角波
+    Triangle,
+    /// のこぎり波
+    Sawtooth,
+    /// 帯域制限した矩形波
+    SquareBl,
+    /// 帯域制限した三角波
+    TriangleBl,
+    /// 帯域制限したのこぎり波
+    SawtoothBl,
+}
+
+/// 倍音として指定できる数(第2倍音から)
+pub const MAX_HARMONICS: usize = 8;
+
+/// 波形と倍音から、位相に応じた値を生成する
+#[derive(Clone, Debug, PartialEq)]
+pub struct Oscillator {
+    /// 波形
+    waveform: Waveform,
+    /// 第2倍音以降の振幅比
+    harmonics: Vec<f32>,
+    /// ナイキスト周波数未満の倍音の次数
+    limit: usize,
+    /// 倍音を加えても振幅が 1 を超えないための係数
+    gain: f32,
+}
+
+impl Oscillator {
+    pub fn new(
+        waveform: Waveform,
+        harmonics: &[f32],
+        frequency: f32,
+        sample_rate: u32,
+    ) -> Oscillator {
+        let limit = ((sample_rate as f32 / 2.0) / frequency).ceil() as usize - 1;
+        let harmonics: Vec<f32> = harmonics
+            .iter()
+            .take(limit.saturating_sub(1))
+            .copied()
+            .collect();
+        let gain = 1.0 / (1.0 + harmonics.iter().map(|a| a.abs()).sum::<f32>());
+
+        return Oscillator {
+            waveform,
+            harmonics,
+            limit: limit.max(1),
+            gain,
+        };
+    }
+
+    /// 位相 `phase` (0.0 .. 1.0) での値(-1.0 .. 1.0)
+    pub fn value(&self, phase: f32) -> f32 {
+        let mut value = self.fundamental(phase);
+
+        for (n, a) in self.harmonics.iter().enumerate() {
+            value += a * (2.0 * PI * (n + 2) as f32 * phase).sin();
+        }
+
+        return value * self.gain;
+    }
+
+    /// 倍音を加える前の波形
+    fn fundamental(&self, phase: f32) -> f32 {
+        // 正弦波と同じく、位相 0 で 0 から立ち上がるようにそろえる
+        match self.waveform {
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Triangle => {
+                if phase < 0.25 {
+                    4.0 * phase
+                } else if phase < 0.75 {
+                    2.0 - 4.0 * phase
+                } else {
+                    4.0 * phase - 4.0
+                }
+            }
+            Waveform::Sawtooth => {
+                if phase < 0.5 {
+                    2.0 * phase
+                } else {
+                    2.0 * phase - 2.0
+                }
+            }
+            Waveform::SquareBl => self.series(phase, |k| {
+                if k % 2 == 1 {
+                    4.0 / (PI * k as f32)
+                } else {
+                    0.0
+                }
+            }),
+            Waveform::TriangleBl => self.series(phase, |k| {
+                if k % 2 == 1 {
+                    let sign = if k % 4 == 1 { 1.0 } else { -1.0 };
+                    sign * 8.0 / (PI * PI * (k * k) as f32)
+                } else {
+                    0.0
+                }
+            }),
+            Waveform::SawtoothBl => self.series(phase, |k| {
+                let sign = if k % 2 == 1 { 1.0 } else { -1.0 };
+                sign * 2.0 / (PI * k as f32)
+            }),
+        }
+    }
+
+    /// ナイキスト周波数未満の倍音のフーリエ級数
+    fn series(&self, phase: f32, coefficient: impl Fn(usize) -> f32) -> f32 {
+        return (1..=self.limit)
+            .map(|k| coefficient(k) * (2.0 * PI * k as f32 * phase).sin())
+            .sum();
+    }
+}
@@ -0,0 +1,484 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+
+use crate::{
+    args::{check_filter, check_range, parse_millis, Args},
+    fading::Fading,
+    fist::Fist,
+    keyer::{Condition, Envelope, Tone},
+    noise::NoiseKind,
+    oscillator::Waveform,
+    random::random_seed,
+    timing::{cpm_to_wpm, Level, Standard, Timing},
+};
+
+/// プレイヤー(電文の送り手)毎の設定
+#[derive(Clone, Debug, PartialEq)]
+pub struct Player {
+    /// 音色
+    pub tone: Tone,
+    /// 受信状態
+    pub condition: Condition,
+    /// 符号の長さの決まり
+    pub timing: Timing,
+    /// 手送りの癖
+    pub fist: Fist,
+    /// 書き出すときのサンプリング周波数[Hz]。デバイスで発音するなら `None`
+    pub sample_rate: Option<u32>,
+}
+
+impl Player {
+    /// コマンドライン・オプションから既定のプレイヤーを作成する
+    pub fn new(opt: &Args) -> Player {
+        return Player {
+            tone: Tone {
+                frequency: opt.frequency,
+                volume: opt.volume,
+                power: opt.power,
+                rise_time: opt.rise_time,
+                envelope: opt.envelope,
+                waveform: opt.waveform,
+                harmonics: opt.harmonics.clone(),
+                chirp: opt.chirp,
+                drift: opt.drift,
+                hum: opt.hum,
+                hum_frequency: opt.hum_frequency,
+            },
+            condition: Condition {
+                noise: opt.noise,
+                snr: opt.snr,
+                qsb: opt.qsb,
+                qsb_depth: opt.qsb_depth,
+                qsb_period: opt.qsb_period,
+                filter: opt.filter,
+                filter_center: opt.frequency + opt.bfo_offset,
+                seed: opt.seed.unwrap_or_else(random_seed),
+            },
+            timing: Timing {
+                wpm: opt.char_wpm(),
+                effective_wpm: opt.effective_wpm,
+                standard: opt.standard,
+                wabun: opt.wabun_ratio,
+                dah_ratio: opt.dah_ratio,
+                weight: opt.weight,
+                farnsworth: opt.farnsworth_timing as f64,
+                wordsworth: opt.wordsworth as f64,
+            },
+            fist: Fist {
+                jitter: opt.jitter,
+                bug: opt.bug,
+            },
+            sample_rate: opt.render_sample_rate(),
+        };
+    }
+
+    /// `#!` 行で指定された項目を反映する
+    ///
+    /// コマンドライン・オプションの範囲チェック機能を利用してオプションの範囲チェックを実施
+    pub fn apply(&mut self, o: &LineOption) -> Result<()> {
+        if let Some(w) = o.frequency {
+            self.tone.frequency = w;
+        }
+        if let Some(w) = o.volume {
+            self.tone.volume = w;
+        }
+        if let Some(w) = o.wpm {
+            self.timing.wpm = w;
+        }
+        if let Some(w) = o.effective_wpm {
+            self.timing.effective_wpm = Some(w);
+        }
+        if let Some(w) = o.standard {
+            self.timing.standard = w;
+        }
+        if let Some(w) = o.wabun_ratio {
+            self.timing.wabun = w;
+        }
+        if let Some(w) = o.farnsworth_timing {
+            self.timing.farnsworth = w as f64;
+        }
+        if let Some(w) = o.wordsworth {
+            self.timing.wordsworth = w as f64;
+        }
+        if let Some(w) = o.rise_time {
+            self.tone.rise_time = w;
+        }
+        if let Some(w) = o.envelope {
+            self.tone.envelope = w;
+        }
+        if let Some(w) = o.waveform {
+            self.tone.waveform = w;
+        }
+        if let Some(ref w) = o.harmonics {
+            self.tone.harmonics = w.clone();
+        }
+        if let Some(w) = o.dah_ratio {
+            self.timing.dah_ratio = w;
+        }
+        if let Some(w) = o.weight {
+            self.timing.weight = w;
+        }
+        if let Some(w) = o.jitter {
+            self.fist.jitter = w;
+        }
+        if let Some(w) = o.bug {
+            self.fist.bug = w;
+        }
+        if let Some(w) = o.chirp {
+            self.tone.chirp = w;
+        }
+        if let Some(w) = o.drift {
+            self.tone.drift = w;
+        }
+        if let Some(w) = o.hum {
+            self.tone.hum = w;
+        }
+        if let Some(w) = o.hum_frequency {
+            self.tone.hum_frequency = w;
+        }
+        if let Some(w) = o.noise {
+            self.condition.noise = w;
+        }
+        if let Some(w) = o.snr {
+            self.condition.snr = Some(w);
+        }
+        if let Some(w) = o.qsb {
+            self.condition.qsb = w;
+        }
+        if let Some(w) = o.qsb_depth {
+            self.condition.qsb_depth = w;
+        }
+        if let Some(w) = o.qsb_period {
+            self.condition.qsb_period = w;
+        }
+
+        // `#!` 行を反映した後の設定全体を確かめる
+        check_range(&self.to_args())?;
+        // 書き出すサンプリング周波数は `Args` には表れないので、フィルタの通過域はここで確かめる
+        check_filter(
+            self.condition.filter,
+            self.condition.filter_center,
+            self.sample_rate,
+        )?;
+
+        return Ok(());
+    }
+
+    /// 現在の設定を、範囲チェック用にコマンドライン・オプションの形にする
+    pub fn to_args(&self) -> Args {
+        let mut opt = Args::default();
+        opt.wpm = self.timing.wpm;
+        opt.effective_wpm = self.timing.effective_wpm;
+        opt.standard = self.timing.standard;
+        opt.wabun_ratio = self.timing.wabun;
+        opt.farnsworth_timing = self.timing.farnsworth as f32;
+        opt.wordsworth = self.timing.wordsworth as f32;
+        opt.dah_ratio = self.timing.dah_ratio;
+        opt.weight = self.timing.weight;
+        opt.frequency = self.tone.frequency;
+        opt.volume = self.tone.volume;
+        opt.power = self.tone.power;
+        opt.rise_time = self.tone.rise_time;
+        opt.envelope = self.tone.envelope;
+        opt.waveform = self.tone.waveform;
+        opt.harmonics = self.tone.harmonics.clone();
+        opt.chirp = self.tone.chirp;
+        opt.drift = self.tone.drift;
+        opt.hum = self.tone.hum;
+        opt.hum_frequency = self.tone.hum_frequency;
+        opt.jitter = self.fist.jitter;
+        opt.bug = self.fist.bug;
+        opt.noise = self.condition.noise;
+        opt.snr = self.condition.snr;
+        opt.qsb = self.condition.qsb;
+        opt.qsb_depth = self.condition.qsb_depth;
+        opt.qsb_period = self.condition.qsb_period;
+        opt.filter = self.condition.filter;
+        // フィルタの中心は受信機の設定なので、音の周波数が変わっても動かない
+        opt.bfo_offset = self.condition.filter_center - self.tone.frequency;
+        opt.seed = Some(self.condition.seed);
+
+        return opt;
+    }
+}
+
+/// `#!` 行で指定されたオプション
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LineOption {
+    pub player: Option<String>,
+    pub frequency: Option<f32>,
+    pub volume: Option<f32>,
+    pub wpm: Option<f64>,
+    pub effective_wpm: Option<f64>,
+    pub standard: Option<Standard>,
+    pub wabun_ratio: Option<f64>,
+    pub farnsworth_timing: Option<f32>,
+    pub wordsworth: Option<f32>,
+    pub rise_time: Option<f32>,
+    pub envelope: Option<Envelope>,
+    pub waveform: Option<Waveform>,
+    pub harmonics: Option<Vec<f32>>,
+    pub dah_ratio: Option<f64>,
+    pub weight: Option<f64>,
+    pub jitter: Option<f64>,
+    pub bug: Option<f64>,
+    pub chirp: Option<f32>,
+    pub drift: Option<f32>,
+    pub hum: Option<f32>,
+    pub hum_frequency: Option<f32>,
+    pub noise: Option<NoiseKind>,
+    pub snr: Option<f32>,
+    pub qsb: Option<Fading>,
+    pub qsb_depth: Option<f32>,
+    pub qsb_period: Option<f32>,
+    /// 次の行を、前の行の開始から何秒後に重ねて発音するか(プレイヤーの設定ではない)
+    pub offset: Option<f64>,
+}
+
+/// 値を取り込む。重複指定や数値として解釈できない場合はエラー
+fn set_once<T: FromStr>(slot: &mut Option<T>, value: Option<&str>, name: &str) -> Result<()> {
+    return set_once_with(slot, value, name, |v| {
+        return v
+            .parse()
+            .map_err(|_| anyhow!("Warning: Invalid `{}`({}).", name, v));
+    });
+}
+
+/// 選択肢の値を取り込む。重複指定や選択肢にない場合はエラー
+fn set_once_enum<T: ValueEnum>(
+    slot: &mut Option<T>,
+    value: Option<&str>,
+    name: &str,
+) -> Result<()> {
+    return set_once_with(slot, value, name, parse_enum);
+}
+
+/// `parse` で解釈した値を取り込む。重複指定の場合はエラー
+fn set_once_with<T>(
+    slot: &mut Option<T>,
+    value: Option<&str>,
+    name: &str,
+    parse: impl Fn(&str) -> Result<T>,
+) -> Result<()> {
+    if slot.is_some() {
+        return Err(anyhow!("Warning: Multiple `{}` are defined.", name));
+    }
+    if let Some(v) = value {
+        *slot = Some(parse(v)?);
+    }
+
+    return Ok(());
+}
+
+/// 選択肢の値を解釈する(大文字・小文字は区別しない)
+fn parse_enum<T: ValueEnum>(v: &str) -> Result<T> {
+    return T::from_str(v, true).map_err(|e| anyhow!("Warning: {}", e));
+}
+
+impl LineOption {
+    /// `#!` 以降の文字列を解釈する
+    pub fn parse(text: &str) -> Result<LineOption> {
+        let mut o = LineOption::default();
+        let mut s = text.split_whitespace();
+
+        while let Some(w) = s.next() {
+            match w.to_lowercase().as_str() {
+                "--frequency" => set_once(&mut o.frequency, s.next(), "frequency")?,
+                "--volume" => set_once(&mut o.volume, s.next(), "volume")?,
+                "--wpm" | "--char-wpm" | "--char_wpm" => set_once(&mut o.wpm, s.next(), "wpm")?,
+                "--cpm" => {
+                    // wpm に換算して取り込む
+                    set_once(&mut o.wpm, s.next(), "wpm")?;
+                    o.wpm = o.wpm.map(cpm_to_wpm);
+                }
+                "--level" => set_once_with(&mut o.wpm, s.next(), "wpm", |v| {
+                    // wpm に換算して取り込む
+                    return Ok(cpm_to_wpm(parse_enum::<Level>(v)?.cpm()));
+                })?,
+                "--wabun-ratio" | "--wabun_ratio" => {
+                    set_once(&mut o.wabun_ratio, s.next(), "wabun ratio")?
+                }
+                "--effective-wpm" | "--effective_wpm" => {
+                    set_once(&mut o.effective_wpm, s.next(), "effective wpm")?
+                }
+                "--standard" => set_once_enum(&mut o.standard, s.next(), "standard")?,
+                "--farnsworth_timing" | "--farnsworth-timing" => {
+                    set_once(&mut o.farnsworth_timing, s.next(), "farnsworth timing")?
+                }
+                "--wordsworth" => set_once(&mut o.wordsworth, s.next(), "wordsworth")?,
+                "--rise-time" | "--rise_time" => {
+                    set_once_with(&mut o.rise_time, s.next(), "rise time", |v| {
+                        return parse_millis(v).map_err(|e| anyhow!("Warning: {}", e));
+                    })?
+                }
+                "--envelope" => set_once_enum(&mut o.envelope, s.next(), "envelope")?,
+                "--waveform" => set_once_enum(&mut o.waveform, s.next(), "waveform")?,
+                "--harmonics" => set_once_with(&mut o.harmonics, s.next(), "harmonics", |v| {
+                    return v
+                        .split(',')
+                        .map(|a| a.trim().parse())
+                        .collect::<Result<Vec<f32>, _>>()
+                        .map_err(|_| anyhow!("Warning: Invalid `harmonics`({}).", v));
+                })?,
+                "--dah-ratio" | "--dah_ratio" => set_once(&mut o.dah_ratio, s.next(), "dah ratio")?,
+                "--weight" => set_once(&mut o.weight, s.next(), "weight")?,
+                "--jitter" => set_once(&mut o.jitter, s.next(), "jitter")?,
+                "--bug" => set_once(&mut o.bug, s.next(), "bug")?,
+                "--chirp" => set_once(&mut o.chirp, s.next(), "chirp")?,
+                "--drift" => set_once(&mut o.drift, s.next(), "drift")?,
+                "--hum" => set_once(&mut o.hum, s.next(), "hum")?,
+                "--hum-frequency" | "--hum_frequency" => {
+                    set_once(&mut o.hum_frequency, s.next(), "hum frequency")?
+                }
+                "--noise" => set_once_enum(&mut o.noise, s.next(), "noise")?,
+                "--snr" => set_once(&mut o.snr, s.next(), "snr")?,
+                "--qsb" => set_once_enum(&mut o.qsb, s.next(), "qsb")?,
+                "--qsb-depth" | "--qsb_depth" => set_once(&mut o.qsb_depth, s.next(), "qsb depth")?,
+                "--qsb-period" | "--qsb_period" => {
+                    set_once(&mut o.qsb_period, s.next(), "qsb period")?
+                }
+                "--offset" => {
+                    set_once(&mut o.offset, s.next(), "offset")?;
+                    if o.offset.is_some_and(|v| v < 0.0) {
+                        return Err(anyhow!("Warning: `offset` must not be negative."));
+                    }
+                }
+                "--player" => set_once(&mut o.player, s.next().map(|v| v.trim()), "player")?,
+                // 想定外のものはエラー
+                _ => {
+                    return Err(anyhow!("Warning: Undefined word({}).", w));
+                }
+            }
+        }
+
+        return Ok(o);
+    }
+
+    /// プレイヤーの設定項目が一つでも指定されているか
+    pub fn has_settings(&self) -> bool {
+        return self.frequency.is_some()
+            || self.volume.is_some()
+            || self.wpm.is_some()
+            || self.effective_wpm.is_some()
+            || self.standard.is_some()
+            || self.wabun_ratio.is_some()
+            || self.farnsworth_timing.is_some()
+            || self.wordsworth.is_some()
+            || self.rise_time.is_some()
+            || self.envelope.is_some()
+            || self.waveform.is_some()
+            || self.harmonics.is_some()
+            || self.dah_ratio.is_some()
+            || self.weight.is_some()
+            || self.jitter.is_some()
+            || self.bug.is_some()
+            || self.chirp.is_some()
+            || self.drift.is_some()
+            || self.hum.is_some()
+            || self.hum_frequency.is_some()
+            || self.noise.is_some()
+            || self.snr.is_some()
+            || self.qsb.is_some()
+            || self.qsb_depth.is_some()
+            || self.qsb_period.is_some();
+    }
+}
+
+/// オプション内容を `#!` 行の形式で出力
+impl fmt::Display for LineOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#!")?;
+
+        if let Some(ref player) = self.player {
+            write!(f, " --player {}", player)?;
+        }
+        if let Some(frequency) = self.frequency {
+            write!(f, " --frequency {}", frequency)?;
+        }
+        if let Some(volume) = self.volume {
+            write!(f, " --volume {}", volume)?;
+        }
+        if let Some(wpm) = self.wpm {
+            write!(f, " --wpm {}", wpm)?;
+        }
+        if let Some(effective_wpm) = self.effective_wpm {
+            write!(f, " --effective-wpm {}", effective_wpm)?;
+        }
+        if let Some(standard) = self.standard {
+            let value = standard.to_possible_value().unwrap();
+            write!(f, " --standard {}", value.get_name())?;
+        }
+        if let Some(wabun_ratio) = self.wabun_ratio {
+            write!(f, " --wabun-ratio {}", wabun_ratio)?;
+        }
+        if let Some(farnsworth_timing) = self.farnsworth_timing {
+            write!(f, " --farnsworth_timing {}", farnsworth_timing)?;
+        }
+        if let Some(wordsworth) = self.wordsworth {
+            write!(f, " --wordsworth {}", wordsworth)?;
+        }
+        if let Some(rise_time) = self.rise_time {
+            write!(f, " --rise-time {}ms", rise_time)?;
+        }
+        if let Some(envelope) = self.envelope {
+            let value = envelope.to_possible_value().unwrap();
+            write!(f, " --envelope {}", value.get_name())?;
+        }
+        if let Some(waveform) = self.waveform {
+            let value = waveform.to_possible_value().unwrap();
+            write!(f, " --waveform {}", value.get_name())?;
+        }
+        if let Some(ref harmonics) = self.harmonics {
+            let values: Vec<String> = harmonics.iter().map(|a| a.to_string()).collect();
+            write!(f, " --harmonics {}", values.join(","))?;
+        }
+        if let Some(dah_ratio) = self.dah_ratio {
+            write!(f, " --dah-ratio {}", dah_ratio)?;
+        }
+        if let Some(weight) = self.weight {
+            write!(f, " --weight {}", weight)?;
+        }
+        if let Some(jitter) = self.jitter {
+            write!(f, " --jitter {}", jitter)?;
+        }
+        if let Some(bug) = self.bug {
+            write!(f, " --bug {}", bug)?;
+        }
+        if let Some(chirp) = self.chirp {
+            write!(f, " --chirp {}", chirp)?;
+        }
+        if let Some(drift) = self.drift {
+            write!(f, " --drift {}", drift)?;
+        }
+        if let Some(hum) = self.hum {
+            write!(f, " --hum {}", hum)?;
+        }
+        if let Some(hum_frequency) = self.hum_frequency {
+            write!(f, " --hum-frequency {}", hum_frequency)?;
+        }
+        if let Some(noise) = self.noise {
+            let value = noise.to_possible_value().unwrap();
+            write!(f, " --noise {}", value.get_name())?;
+        }
+        if let Some(snr) = self.snr {
+            write!(f, " --snr {}", snr)?;
+        }
+        if let Some(qsb) = self.qsb {
+            let value = qsb.to_possible_value().unwrap();
+            write!(f, " --qsb {}", value.get_name())?;
+        }
+        if let Some(qsb_depth) = self.qsb_depth {
+            write!(f, " --qsb-depth {}", qsb_depth)?;
+        }
+        if let Some(qsb_period) = self.qsb_period {
+            write!(f, " --qsb-period {}", qsb_period)?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, " --offset {}", offset)?;
+        }
+
+        return Ok(());
+    }
+}
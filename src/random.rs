@@ -0,0 +1,63 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 再現可能な擬似乱数(xorshift64*)
+///
+/// 練習用の雑音やフェージングを、同じシードで同じように再生するために使う。
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        // 近いシード同士でも系列が似ないよう、splitmix64 で初期状態を作る
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+
+        return Rng {
+            state: if z == 0 { 1 } else { z },
+        };
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+
+        return self.state.wrapping_mul(0x2545_f491_4f6c_dd1d);
+    }
+
+    /// 一様分布(0.0 .. 1.0)
+    pub fn uniform(&mut self) -> f64 {
+        return (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+    }
+
+    /// 標準正規分布(平均 0、分散 1)
+    pub fn gaussian(&mut self) -> f64 {
+        // Box-Muller 法。log(0) を避けるため 1 - uniform を使う
+        let r = (-2.0 * (1.0 - self.uniform()).ln()).sqrt();
+        let theta = 2.0 * std::f64::consts::PI * self.uniform();
+
+        return r * theta.cos();
+    }
+}
+
+/// シードの指定がない場合に使う、実行毎に異なるシード
+pub fn random_seed() -> u64 {
+    return SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+}
+
+/// プレイヤー毎のシードを、元のシードと名前から求める(FNV-1a)
+pub fn player_seed(seed: u64, name: &str) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325 ^ seed;
+    for byte in name.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    return hash;
+}
@@ -0,0 +1,133 @@
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+
+use anyhow::Result;
+use clap::ValueEnum;
+
+use crate::{
+    keyer::{Keyer, Mark, Timeline},
+    sink::Sink,
+};
+
+/// 生 PCM のサンプル形式(リトルエンディアン)
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum RawFormat {
+    /// 符号付き 16bit 整数
+    S16,
+    /// 32bit 浮動小数点
+    F32,
+}
+
+impl RawFormat {
+    /// 1サンプルのバイト数
+    fn size(&self) -> usize {
+        return match self {
+            RawFormat::S16 => 2,
+            RawFormat::F32 => 4,
+        };
+    }
+}
+
+/// 生 PCM (モノラル)を読み込み、サンプル(-1.0 .. 1.0)を順に返すイテレーター
+///
+/// パイプから読めた分だけ返すので、`arecord` などの終わりのない入力にも使える。
+pub struct RawSource<R: Read> {
+    reader: BufReader<R>,
+    /// サンプル形式
+    format: RawFormat,
+}
+
+impl<R: Read> RawSource<R> {
+    pub fn new(reader: R, format: RawFormat) -> RawSource<R> {
+        return RawSource {
+            reader: BufReader::new(reader),
+            format,
+        };
+    }
+}
+
+impl<R: Read> Iterator for RawSource<R> {
+    type Item = Result<f32>;
+
+    fn next(&mut self) -> Option<Result<f32>> {
+        let mut bytes = [0u8; 4];
+        let bytes = &mut bytes[..self.format.size()];
+
+        if let Err(e) = self.reader.read_exact(bytes) {
+            // 終端(端数のバイトは捨てる)
+            if e.kind() == ErrorKind::UnexpectedEof {
+                return None;
+            }
+            return Some(Err(e.into()));
+        }
+
+        return Some(Ok(match self.format {
+            RawFormat::S16 => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / i16::MAX as f32,
+            RawFormat::F32 => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }));
+    }
+}
+
+/// モールス音を生 PCM (モノラル)として書き出す
+///
+/// `sox` や `aplay` などへパイプで渡すために使う。
+pub struct RawSink<W: Write> {
+    writer: BufWriter<W>,
+    /// サンプル形式
+    format: RawFormat,
+    /// サンプリング周波数
+    sample_rate: u32,
+    /// タイムラインから波形を生成
+    keyer: Keyer,
+}
+
+impl<W: Write> RawSink<W> {
+    pub fn new(writer: W, format: RawFormat, sample_rate: u32) -> RawSink<W> {
+        return RawSink {
+            writer: BufWriter::new(writer),
+            format,
+            sample_rate,
+            keyer: Keyer::new(sample_rate),
+        };
+    }
+
+    /// 確定したところまで波形にして書き出す。`all` なら最後まで
+    fn render(&mut self, all: bool, on_mark: &mut dyn FnMut(&Mark)) -> Result<()> {
+        let mut marks = Vec::new();
+
+        while let Some(value) = self.keyer.next_sample_within(all, &mut marks) {
+            match self.format {
+                RawFormat::S16 => {
+                    let sample = (value * i16::MAX as f32) as i16;
+                    self.writer.write_all(&sample.to_le_bytes())?;
+                }
+                RawFormat::F32 => {
+                    self.writer.write_all(&value.to_le_bytes())?;
+                }
+            }
+        }
+        marks.iter().for_each(on_mark);
+
+        return Ok(());
+    }
+}
+
+impl<W: Write> Sink for RawSink<W> {
+    fn sample_rate(&self) -> u32 {
+        return self.sample_rate;
+    }
+
+    /// タイムラインを波形にして書き出す
+    fn send(&mut self, timeline: Timeline, on_mark: &mut dyn FnMut(&Mark)) -> Result<()> {
+        self.keyer.push(timeline);
+
+        return self.render(false, on_mark);
+    }
+
+    /// 残りを書き出す
+    fn finish(&mut self, on_mark: &mut dyn FnMut(&Mark)) -> Result<()> {
+        self.render(true, on_mark)?;
+        self.writer.flush()?;
+
+        return Ok(());
+    }
+}
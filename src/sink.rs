@@ -0,0 +1,357 @@
+use std::{
+    path::Path,
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+use anyhow::{anyhow, Result};
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    Device, Host, OutputCallbackInfo, Sample, Stream, StreamConfig,
+};
+
+use crate::keyer::{Keyer, Mark, Timeline};
+
+/// モールス音の出力先
+///
+/// `Morse` は一行毎にタイムラインを作成して出力先へ送る。
+/// 通過した目印(発音中の行や文字)は、出力先が `on_mark` で知らせる。
+pub trait Sink {
+    /// サンプリング周波数
+    fn sample_rate(&self) -> u32;
+
+    /// タイムラインを出力する
+    fn send(&mut self, timeline: Timeline, on_mark: &mut dyn FnMut(&Mark)) -> Result<()>;
+
+    /// 出力を完了する
+    fn finish(&mut self, on_mark: &mut dyn FnMut(&Mark)) -> Result<()>;
+}
+
+/// 出力ファイルの形式
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FileFormat {
+    Wav,
+    Flac,
+    Ogg,
+}
+
+impl FileFormat {
+    /// 拡張子から形式を判定する
+    pub fn from_path(path: &Path) -> Option<FileFormat> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+
+        return match ext.as_str() {
+            "wav" => Some(FileFormat::Wav),
+            "flac" => Some(FileFormat::Flac),
+            "ogg" | "oga" => Some(FileFormat::Ogg),
+            _ => None,
+        };
+    }
+}
+
+/// 出力ファイルに書き込むタグ
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tags {
+    /// 題名
+    pub title: String,
+    /// 既定のプレイヤーの wpm
+    pub wpm: f64,
+    /// 既定のプレイヤーの周波数
+    pub frequency: f32,
+}
+
+impl Tags {
+    /// Vorbis comment 形式の (項目, 値) の並び
+    pub fn comments(&self) -> Vec<(&'static str, String)> {
+        return vec![
+            ("TITLE", self.title.clone()),
+            ("WPM", self.wpm.to_string()),
+            ("FREQUENCY", self.frequency.to_string()),
+            ("ENCODER", format!("morse-rs {}", env!("CARGO_PKG_VERSION"))),
+        ];
+    }
+}
+
+/// 名前で指定されたホスト、または既定のホストを取得する
+fn select_host(name: Option<&str>) -> Result<Host> {
+    let Some(name) = name else {
+        return Ok(cpal::default_host());
+    };
+
+    let id = cpal::available_hosts()
+        .into_iter()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            let names: Vec<&str> = cpal::available_hosts().iter().map(|id| id.name()).collect();
+            anyhow!(
+                "error: host({}) is not available. ( {} )",
+                name,
+                names.join(", ")
+            )
+        })?;
+
+    return Ok(cpal::host_from_id(id)?);
+}
+
+/// 名前で指定された出力デバイス、または既定の出力デバイスを取得する
+fn select_device(host: &Host, name: Option<&str>) -> Result<Device> {
+    let Some(name) = name else {
+        return host
+            .default_output_device()
+            .ok_or_else(|| anyhow!("error: failed to find output device."));
+    };
+
+    for device in host.output_devices()? {
+        if device.name().is_ok_and(|n| n == name) {
+            return Ok(device);
+        }
+    }
+
+    return Err(anyhow!(
+        "error: output device({}) is not found. (see `--list-devices`)",
+        name
+    ));
+}
+
+/// ホストと出力デバイス、対応している設定を一覧表示する
+pub fn list_devices() -> Result<()> {
+    let default_host = cpal::default_host().id();
+
+    for id in cpal::available_hosts() {
+        let mark = if id == default_host { " (default)" } else { "" };
+        println!("{}{}", id.name(), mark);
+
+        let host = match cpal::host_from_id(id) {
+            Ok(host) => host,
+            Err(e) => {
+                println!("  {}", e);
+                continue;
+            }
+        };
+        let default_device = host.default_output_device().and_then(|d| d.name().ok());
+
+        for device in host.output_devices()? {
+            let name = device.name()?;
+            let mark = if Some(&name) == default_device.as_ref() {
+                " (default)"
+            } else {
+                ""
+            };
+            println!("  {}{}", name, mark);
+
+            if let Ok(configs) = device.supported_output_configs() {
+                for config in configs {
+                    println!(
+                        "    channels: {}, sample rate: {} .. {}, format: {}",
+                        config.channels(),
+                        config.min_sample_rate().0,
+                        config.max_sample_rate().0,
+                        config.sample_format()
+                    );
+                }
+            }
+        }
+    }
+
+    return Ok(());
+}
+
+/// 発音用の stream を生成する
+///
+/// キーイングはコールバック内で、受け取ったタイムラインに従ってサンプル単位で行う。
+/// 通過した目印はコールバックから送り返す。
+fn genarate_stream(
+    host: Option<&str>,
+    device: Option<&str>,
+    timelines: Receiver<Timeline>,
+    marks: Sender<Mark>,
+) -> Result<(Stream, u32)> {
+    fn write_data(output: &mut [f32], channels: usize, next_sample: &mut dyn FnMut() -> f32) {
+        for frame in output.chunks_mut(channels) {
+            let value: f32 = f32::from_sample(next_sample());
+            for sample in frame.iter_mut() {
+                *sample = value;
+            }
+        }
+    }
+
+    let host = select_host(host)?;
+
+    let device = select_device(&host, device)?;
+
+    let config: StreamConfig = device.default_output_config()?.into();
+
+    let sample_rate = config.sample_rate.0;
+    let channels = config.channels as usize;
+
+    let err_fn = |err| eprintln!("an error occurred on stream: {}", err);
+
+    let mut keyer = Keyer::new(sample_rate);
+    let mut passed = Vec::new();
+
+    let stream = device.build_output_stream(
+        &config,
+        move |data: &mut [f32], _: &OutputCallbackInfo| {
+            while let Ok(timeline) = timelines.try_recv() {
+                keyer.push(timeline);
+            }
+
+            write_data(data, channels, &mut || {
+                keyer.next_sample(&mut passed).unwrap_or(0.0)
+            });
+
+            for mark in passed.drain(..) {
+                // 受け手がいなくなっていれば知らせる必要はない
+                let _ = marks.send(mark);
+            }
+        },
+        err_fn,
+        None,
+    )?;
+
+    return Ok((stream, sample_rate));
+}
+
+/// cpal によるリアルタイム発音
+pub struct CpalSink {
+    /// 発音中の stream (破棄すると発音が止まる)
+    stream: Stream,
+    /// サンプリング周波数
+    sample_rate: u32,
+    /// コールバックへタイムラインを送る
+    timelines: Sender<Timeline>,
+    /// コールバックから通過した目印を受け取る
+    marks: Receiver<Mark>,
+    /// 発音が始まっていないタイムラインの数
+    unstarted: usize,
+    /// 発音が終わっていないタイムラインの数
+    pending: usize,
+}
+
+impl CpalSink {
+    /// ホストと出力デバイスを名前で指定する。`None` なら既定のものを使う
+    pub fn new(host: Option<&str>, device: Option<&str>) -> Result<CpalSink> {
+        let (timelines, timeline_receiver) = channel();
+        let (mark_sender, marks) = channel();
+        let (stream, sample_rate) = genarate_stream(host, device, timeline_receiver, mark_sender)?;
+        stream.play()?;
+
+        return Ok(CpalSink {
+            stream,
+            sample_rate,
+            timelines,
+            marks,
+            unstarted: 0,
+            pending: 0,
+        });
+    }
+
+    /// 送ったタイムラインの発音がすべて始まる(`all` ならすべて終わる)まで待つ
+    fn wait(&mut self, all: bool, on_mark: &mut dyn FnMut(&Mark)) -> Result<()> {
+        while self.unstarted > 0 || (all && self.pending > 0) {
+            let mark = self.marks.recv()?;
+            match mark {
+                Mark::Start => self.unstarted -= 1,
+                Mark::End => self.pending -= 1,
+                _ => {}
+            }
+            on_mark(&mark);
+        }
+
+        return Ok(());
+    }
+}
+
+impl Sink for CpalSink {
+    fn sample_rate(&self) -> u32 {
+        return self.sample_rate;
+    }
+
+    fn send(&mut self, timeline: Timeline, on_mark: &mut dyn FnMut(&Mark)) -> Result<()> {
+        self.timelines.send(timeline)?;
+        self.unstarted += 1;
+        self.pending += 1;
+
+        // 途切れないように、また重ねて発音する次の行に間に合うように、
+        // 送った行の発音が始まった時点で次の行を受け付ける
+        return self.wait(false, on_mark);
+    }
+
+    fn finish(&mut self, on_mark: &mut dyn FnMut(&Mark)) -> Result<()> {
+        self.wait(true, on_mark)?;
+        self.stream.pause()?;
+
+        return Ok(());
+    }
+}
+
+/// 何も出力しない出力先
+///
+/// 目印はタイムラインを受け取った時点でまとめて知らせる。
+pub struct NullSink {
+    /// サンプリング周波数
+    sample_rate: u32,
+}
+
+impl NullSink {
+    pub fn new(sample_rate: u32) -> NullSink {
+        return NullSink { sample_rate };
+    }
+}
+
+impl Sink for NullSink {
+    fn sample_rate(&self) -> u32 {
+        return self.sample_rate;
+    }
+
+    fn send(&mut self, timeline: Timeline, on_mark: &mut dyn FnMut(&Mark)) -> Result<()> {
+        on_mark(&Mark::Start);
+        timeline.marks().iter().for_each(|(_, mark)| on_mark(mark));
+        on_mark(&Mark::End);
+
+        return Ok(());
+    }
+
+    fn finish(&mut self, _: &mut dyn FnMut(&Mark)) -> Result<()> {
+        return Ok(());
+    }
+}
+
+/// 受け取ったタイムラインをそのまま記録する出力先
+///
+/// 何が発音されるはずだったかを、キーイングの単位で確認するために使う。
+pub struct RecordingSink {
+    /// 目印の通知は `NullSink` と同じ
+    null: NullSink,
+    /// 受け取ったタイムライン
+    timelines: Vec<Timeline>,
+}
+
+impl RecordingSink {
+    pub fn new(sample_rate: u32) -> RecordingSink {
+        return RecordingSink {
+            null: NullSink::new(sample_rate),
+            timelines: Vec::new(),
+        };
+    }
+
+    /// 受け取ったタイムライン(一行毎)
+    pub fn timelines(&self) -> &[Timeline] {
+        return &self.timelines;
+    }
+}
+
+impl Sink for RecordingSink {
+    fn sample_rate(&self) -> u32 {
+        return self.null.sample_rate();
+    }
+
+    fn send(&mut self, timeline: Timeline, on_mark: &mut dyn FnMut(&Mark)) -> Result<()> {
+        self.timelines.push(timeline.clone());
+
+        return self.null.send(timeline, on_mark);
+    }
+
+    fn finish(&mut self, on_mark: &mut dyn FnMut(&Mark)) -> Result<()> {
+        return self.null.finish(on_mark);
+    }
+}
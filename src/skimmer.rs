@@ -0,0 +1,99 @@
+use crate::{
+    decoder::{Decoded, Decoder, BREAK_TIME},
+    detector::{find_signals, Goertzel, ToneDetector, BLOCK_TIME},
+    filter::CwFilter,
+};
+
+/// 各信号を取り出すフィルタの帯域幅[Hz]
+///
+/// 信号の間隔(50Hz 以上)に合わせる。隣の強い信号は 80Hz 離れていれば約 40dB 落ちる。
+const CHANNEL_WIDTH: f32 = 50.0;
+/// 信号の最も強いところに対する、音とみなす強さの下限(振幅比、-10dB)
+///
+/// 隣の信号のキークリックを拾わないよう、深めにかける。
+const SQUELCH_RATIO: f32 = 0.3;
+
+/// 一つの信号の、一回分の送信
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transmission {
+    /// 信号の周波数[Hz]
+    pub frequency: f32,
+    /// 録音の先頭からの送信の開始時刻[秒]
+    pub start: f64,
+    /// 復号した結果
+    pub decoded: Decoded,
+}
+
+/// 録音の中の信号(`low` .. `high` [Hz])をすべて探し、それぞれを復号する
+///
+/// 信号毎に CW フィルタで取り出してキーイングを求め、2 秒以上の無音で送信を区切る。
+/// 送信の開始時刻の順(同時なら周波数の低い順)に返す。
+pub fn skim(
+    samples: &[f32],
+    sample_rate: u32,
+    low: f32,
+    high: f32,
+    decoder: &Decoder,
+) -> Vec<Transmission> {
+    let mut transmissions = vec![];
+
+    for frequency in find_signals(samples, sample_rate, low, high) {
+        let mut filter = CwFilter::new(sample_rate, frequency, CHANNEL_WIDTH);
+        let channel: Vec<f32> = samples.iter().map(|x| filter.process(*x)).collect();
+
+        let mut detector = ToneDetector::new(frequency, sample_rate, BLOCK_TIME);
+        detector.set_squelch(SQUELCH_RATIO * strength(&channel, frequency, sample_rate));
+        let keying = detector.keying(&channel);
+
+        for (start, segment) in split(&keying) {
+            transmissions.push(Transmission {
+                frequency,
+                start,
+                decoded: decoder.decode_keying(segment),
+            });
+        }
+    }
+    transmissions.sort_by(|a, b| {
+        a.start
+            .total_cmp(&b.start)
+            .then(a.frequency.total_cmp(&b.frequency))
+    });
+
+    return transmissions;
+}
+
+/// 信号の最も強いところの振幅
+fn strength(channel: &[f32], frequency: f32, sample_rate: u32) -> f32 {
+    let block = (BLOCK_TIME * sample_rate as f32).round() as usize;
+    let mut goertzel = Goertzel::new(frequency, sample_rate, block);
+
+    return channel
+        .iter()
+        .filter_map(|x| goertzel.push(*x))
+        .fold(0.0, f32::max);
+}
+
+/// キーイングを長い無音で区切り、送信毎の開始時刻[秒]とキーイングを返す
+fn split(keying: &[(bool, f64)]) -> Vec<(f64, &[(bool, f64)])> {
+    let mut segments = vec![];
+    let mut time = 0.0;
+    // 送信中なら、その開始時刻とキーイングの位置
+    let mut current: Option<(f64, usize)> = None;
+
+    for (k, (key_down, duration)) in keying.iter().enumerate() {
+        match current {
+            None if *key_down => current = Some((time, k)),
+            Some((start, first)) if !key_down && BREAK_TIME <= *duration => {
+                segments.push((start, &keying[first..k]));
+                current = None;
+            }
+            _ => {}
+        }
+        time += duration;
+    }
+    if let Some((start, first)) = current {
+        segments.push((start, &keying[first..]));
+    }
+
+    return segments;
+}
@@ -0,0 +1,186 @@
+use crate::{
+    decoder::{Copied, Copier, Decoder, BREAK_TIME},
+    detector::{detect_tone, ToneDetector, BLOCK_TIME},
+};
+
+/// 音の周波数を探す間隔[秒]
+const SEARCH_INTERVAL: f64 = 1.0;
+/// 音の周波数を探すために溜めておく最大の長さ[秒]
+const SEARCH_KEEP: f64 = 10.0;
+
+/// 聞き取ったもの
+#[derive(Clone, Debug, PartialEq)]
+pub enum Heard {
+    /// 確定した文字列
+    Text(String),
+    /// 長い無音(送信の区切り)
+    Break,
+}
+
+/// サンプルを少しずつ受け取りながら復号する
+///
+/// 音の周波数を指定しなければ、雑音より強い信号が現れるまでサンプルを溜めて探す。
+/// 指定しても、最初の 1 秒は信号の強さを調べるために溜める。
+/// 和文の濁点・半濁点と合わせうる文字は、次の文字が分かるまで返さない。
+pub struct StreamDecoder {
+    decoder: Decoder,
+    sample_rate: u32,
+    /// 聞き取っている音の周波数[Hz]
+    tone: Option<f32>,
+    /// 音の周波数が決まるまで溜めておくサンプル
+    buffer: Vec<f32>,
+    detector: Option<ToneDetector>,
+    copier: Copier,
+    /// 送信中に読み取った符号
+    line: Vec<Copied>,
+    /// `line` を復号した文字列のうち、返した文字数
+    returned: usize,
+}
+
+impl StreamDecoder {
+    pub fn new(decoder: Decoder, sample_rate: u32, tone: Option<f32>) -> StreamDecoder {
+        return StreamDecoder {
+            decoder,
+            sample_rate,
+            tone,
+            buffer: vec![],
+            detector: None,
+            copier: Copier::new(),
+            line: vec![],
+            returned: 0,
+        };
+    }
+
+    /// 聞き取っている音の周波数[Hz]
+    pub fn tone(&self) -> Option<f32> {
+        return self.tone;
+    }
+
+    /// 推定した速度[wpm]
+    pub fn wpm(&self) -> Option<f64> {
+        return self.copier.wpm();
+    }
+
+    /// サンプルを加え、聞き取れたものを返す
+    pub fn push(&mut self, x: f32) -> Vec<Heard> {
+        let Some(ref mut detector) = self.detector else {
+            return self.search(x);
+        };
+
+        let mut copied = vec![];
+        if let Some((key_down, duration)) = detector.push(x) {
+            copied.extend(self.copier.push(key_down, duration));
+        }
+        // 無音が続いていれば、次にキーを押すのを待たずに文字を確定する
+        let (key_down, duration) = detector.state();
+        if !key_down {
+            copied.extend(self.copier.silence(duration));
+        }
+        let mut heard = self.hear(copied, false);
+
+        if !key_down && BREAK_TIME <= duration && !self.line.is_empty() {
+            heard.extend(self.hear(vec![], true));
+            heard.push(Heard::Break);
+            self.line.clear();
+            self.returned = 0;
+        }
+
+        return heard;
+    }
+
+    /// 入力の終わりに、残りをすべて返す
+    pub fn finish(&mut self) -> Vec<Heard> {
+        let mut heard = vec![];
+
+        if self.detector.is_none() && !self.buffer.is_empty() {
+            // 音の周波数を探す間隔より短い入力
+            self.tone = self
+                .tone
+                .or_else(|| detect_tone(&self.buffer, self.sample_rate));
+            if self.tone.is_none() {
+                return heard;
+            }
+            heard.extend(self.start());
+        }
+        if let Some(ref mut detector) = self.detector {
+            let mut copied = vec![];
+            if let Some((key_down, duration)) = detector.finish() {
+                copied.extend(self.copier.push(key_down, duration));
+            }
+            copied.extend(self.copier.flush());
+            heard.extend(self.hear(copied, true));
+        }
+        if !self.line.is_empty() {
+            heard.push(Heard::Break);
+            self.line.clear();
+            self.returned = 0;
+        }
+
+        return heard;
+    }
+
+    /// 音の周波数が決まるまでサンプルを溜め、決まったら溜めた分から聞き取る
+    fn search(&mut self, x: f32) -> Vec<Heard> {
+        self.buffer.push(x);
+
+        let interval = (SEARCH_INTERVAL * self.sample_rate as f64) as usize;
+        if !self.buffer.len().is_multiple_of(interval) {
+            return vec![];
+        }
+        self.tone = self
+            .tone
+            .or_else(|| detect_tone(&self.buffer, self.sample_rate));
+        if self.tone.is_none() {
+            if SEARCH_KEEP * (self.sample_rate as f64) < self.buffer.len() as f64 {
+                self.buffer.drain(..interval);
+            }
+            return vec![];
+        }
+
+        return self.start();
+    }
+
+    /// 見つけた音の周波数で、溜めたサンプルから聞き取り始める
+    fn start(&mut self) -> Vec<Heard> {
+        let mut detector = ToneDetector::new(self.tone.unwrap(), self.sample_rate, BLOCK_TIME);
+        let buffer = std::mem::take(&mut self.buffer);
+        detector.prime(&buffer);
+        self.detector = Some(detector);
+
+        return buffer.into_iter().flat_map(|x| self.push(x)).collect();
+    }
+
+    /// 読み取った符号を加え、確定した文字列を返す。`all` なら保留している文字も返す
+    fn hear(&mut self, copied: Vec<Copied>, all: bool) -> Vec<Heard> {
+        if copied.is_empty() && !all {
+            return vec![];
+        }
+        self.line.extend(copied);
+
+        // 最後の文字が濁点・半濁点と合わさるかもしれなければ、その前までを確定する
+        let mut end = self.line.len();
+        if !all {
+            if let Some(k) = self.line.iter().rposition(|c| *c != Copied::Space) {
+                if let Copied::Letter(ref code) = self.line[k] {
+                    if self.decoder.is_pair_head(code) {
+                        end = k;
+                    }
+                }
+            }
+        }
+
+        let text: String = self
+            .decoder
+            .decode_copied(&self.line[..end])
+            .text
+            .chars()
+            .skip(self.returned)
+            .collect();
+        if text.is_empty() {
+            return vec![];
+        }
+        self.returned += text.chars().count();
+
+        return vec![Heard::Text(text)];
+    }
+}
@@ -0,0 +1,237 @@
+use clap::ValueEnum;
+
+/// 速度の基準とする語
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum Standard {
+    /// "PARIS " (50短点)
+    #[default]
+    Paris,
+    /// "CODEX " (60短点)
+    Codex,
+}
+
+impl Standard {
+    /// 基準の語(語間を含む)の長さ[短点]
+    pub fn units(&self) -> f64 {
+        return match self {
+            Standard::Paris => 50.0,
+            Standard::Codex => 60.0,
+        };
+    }
+}
+
+/// JARL「モールス電信技能認定」の段位
+///
+/// 英数字の名前(`3kyu`, `shodan` など)でも指定できる。
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Level {
+    #[value(name = "3級", alias = "3kyu")]
+    Kyu3,
+    #[value(name = "2級", alias = "2kyu")]
+    Kyu2,
+    #[value(name = "1級", alias = "1kyu")]
+    Kyu1,
+    #[value(name = "初段", alias = "shodan")]
+    Shodan,
+    #[value(name = "2段", alias = "2dan")]
+    Dan2,
+    #[value(name = "3段", alias = "3dan")]
+    Dan3,
+    #[value(name = "4段", alias = "4dan")]
+    Dan4,
+    #[value(name = "5段", alias = "5dan")]
+    Dan5,
+    #[value(name = "名人位", alias = "meijin")]
+    Meijin,
+}
+
+impl Level {
+    /// 段位の速度[CPM]
+    pub fn cpm(&self) -> f64 {
+        return match self {
+            Level::Kyu3 => 25.0,
+            Level::Kyu2 => 45.0,
+            Level::Kyu1 => 60.0,
+            Level::Shodan => 90.0,
+            Level::Dan2 => 110.0,
+            Level::Dan3 => 120.0,
+            Level::Dan4 => 140.0,
+            Level::Dan5 => 160.0,
+            Level::Meijin => 180.0,
+        };
+    }
+}
+
+/// CPM を wpm に換算する(PARIS の 1 語を 5 文字とする)
+pub fn cpm_to_wpm(cpm: f64) -> f64 {
+    return cpm / 5.0;
+}
+
+/// 基準の語のうち、文字間(3短点 x 4)と語間(7短点)の長さ[短点]
+const SPACE_UNITS: f64 = 19.0;
+
+/// 送れる速度の下限[wpm]
+pub const MIN_WPM: f64 = 3.0;
+/// 送れる速度の上限[wpm]
+pub const MAX_WPM: f64 = 60.0;
+
+/// wpm から短点の長さ[秒]を算出する
+fn calc_dit(wpm: f64, standard: Standard) -> f64 {
+    return 60.0 / (standard.units() * wpm);
+}
+
+/// 符号の長さの決まり
+///
+/// 短点を単位として、長点・符号間・文字間・語間の長さを求める。
+/// 標準は 1:3:1:3:7 で、長点の比・重み・実効速度・文字間と語間の倍率で変えられる。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timing {
+    /// wpm (文字の速度)
+    pub wpm: f64,
+    /// 文字間・語間を含めた実効速度[wpm]
+    pub effective_wpm: Option<f64>,
+    /// 速度の基準とする語
+    pub standard: Standard,
+    /// 和文を送るときの速度の割合[%]
+    pub wabun: f64,
+    /// 短点に対する長点の長さの比
+    pub dah_ratio: f64,
+    /// 短点とその後の間隔のうち、キーを押している割合[%]。50 で等しい
+    pub weight: f64,
+    /// 文字間・語間の長さの倍率(Farnsworth)
+    pub farnsworth: f64,
+    /// 語間だけの長さの倍率(Wordsworth)
+    pub wordsworth: f64,
+}
+
+impl Timing {
+    /// 和文を送るときの決まり(速度を和文の割合に落とす)
+    ///
+    /// ランプで速度の範囲に収めた後に落とすので、3 wpm より遅くなることもある。
+    pub fn for_wabun(&self) -> Timing {
+        return self.scaled(self.wabun / 100.0);
+    }
+
+    /// 文字の速度と実効速度を `ratio` 倍にした決まり
+    pub fn scaled(&self, ratio: f64) -> Timing {
+        return Timing {
+            wpm: self.wpm * ratio,
+            effective_wpm: self.effective_wpm.map(|s| s * ratio),
+            ..*self
+        };
+    }
+
+    /// ランプの倍率を掛けた決まり(速度は範囲チェックと同じ範囲に収める)
+    pub fn ramped(&self, factor: f64) -> Timing {
+        let wpm = (self.wpm * factor).clamp(MIN_WPM, MAX_WPM);
+
+        return self.scaled(wpm / self.wpm);
+    }
+
+    /// 短点の長さ[秒]
+    pub fn dit(&self) -> f64 {
+        return calc_dit(self.wpm, self.standard);
+    }
+
+    /// 短点・長点のキーを押している時間と、続けて離している時間[秒]
+    pub fn element(&self, is_dah: bool) -> (f64, f64) {
+        let dit = self.dit();
+        // 重みの分だけキーを押している時間を伸ばし、その分離している時間を縮める
+        let shift = (self.weight / 50.0 - 1.0) * dit;
+        let down = if is_dah { self.dah_ratio * dit } else { dit };
+
+        return (down + shift, dit - shift);
+    }
+
+    /// 文字間の時間[秒]
+    pub fn letter_gap(&self) -> f64 {
+        return 3.0 * self.space_unit() * self.farnsworth;
+    }
+
+    /// 語間の時間[秒]
+    pub fn word_gap(&self) -> f64 {
+        return 7.0 * self.space_unit() * self.farnsworth * self.wordsworth;
+    }
+
+    /// 文字間・語間の単位時間[秒]
+    ///
+    /// 実効速度の指定があれば ARRL の式で求める。
+    /// 基準の語のうち、文字間・語間の 19 単位を伸ばして実効速度に合わせる。
+    /// (PARIS では `(60 * c - 37.2 * s) / (s * c) / 19`)
+    /// 実効速度が文字の速度以下であることは、コマンドラインでも `#!` 行でも範囲チェックで確かめてある。
+    fn space_unit(&self) -> f64 {
+        return match self.effective_wpm {
+            Some(s) => {
+                let chars = (self.standard.units() - SPACE_UNITS) * self.dit();
+                (60.0 / s - chars) / SPACE_UNITS
+            }
+            None => self.dit(),
+        };
+    }
+}
+
+/// 速度を変えていく単位
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum RampUnit {
+    /// 行数
+    #[default]
+    Line,
+    /// 時間[秒]
+    Time,
+    /// 文字数
+    Char,
+}
+
+/// 速度を徐々に変えていく(ランプ)
+///
+/// 開始の速度から終了の速度まで、指定の行数・時間・文字数をかけて直線的に変える。
+/// 各プレイヤーの速度には、開始の速度に対する比を掛ける。
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ramp {
+    /// 開始の速度に対する終了の速度の比
+    ratio: f64,
+    /// 速度を変え終えるまでの量
+    over: f64,
+    /// 量の単位
+    unit: RampUnit,
+    /// これまでに送った量
+    progress: f64,
+}
+
+impl Default for Ramp {
+    /// 速度を変えない
+    fn default() -> Ramp {
+        return Ramp {
+            ratio: 1.0,
+            over: 1.0,
+            unit: RampUnit::default(),
+            progress: 0.0,
+        };
+    }
+}
+
+impl Ramp {
+    /// `start` [wpm] から `end` [wpm] まで、`over` [unit] かけて変える
+    pub fn new(start: f64, end: f64, over: f64, unit: RampUnit) -> Ramp {
+        return Ramp {
+            ratio: end / start,
+            over,
+            unit,
+            progress: 0.0,
+        };
+    }
+
+    /// 現在の速度の倍率
+    pub fn factor(&self) -> f64 {
+        let progress = (self.progress / self.over).min(1.0);
+
+        return 1.0 + (self.ratio - 1.0) * progress;
+    }
+
+    /// 送った量を進める(単位が違うものは無視する)
+    pub fn advance(&mut self, unit: RampUnit, amount: f64) {
+        if unit == self.unit {
+            self.progress += amount;
+        }
+    }
+}
@@ -1,9 +1,16 @@
 use std::collections::HashMap;
 
-/// 文字 -> モールスコード変換テーブルを作成する
-pub fn set_translation_table() -> HashMap<char, &'static str> {
-    let mut table = HashMap::new();
-    let tbl = vec![
+/// 和文(ひらがな・カタカナ)の文字か
+pub fn is_kana(ch: char) -> bool {
+    return ('\u{3041}'..='\u{30FF}').contains(&ch);
+}
+
+/// 文字とモールスコードの対応の一覧
+///
+/// 同じ符号の文字が複数ある場合、先に書かれたものを逆変換(復号)で優先する。
+/// 各項目の先頭の文字(大文字・カタカナ)が、その符号を代表する。
+pub fn translation_entries() -> Vec<(Vec<char>, &'static str)> {
+    return vec![
         (vec![' '], " "),
         //
         // 英文字
@@ -162,8 +169,8 @@ pub fn set_translation_table() -> HashMap<char, &'static str> {
         //
         // かな記号
         // jscwlib.jsから転記。注記追加変更
-        (vec!['－'], ".--.-"),  /* 長音とハイフンは同じモールス符号 */
         (vec!['ー'], ".--.-"),  /* 長音とハイフンは同じモールス符号 */
+        (vec!['－'], ".--.-"),  /* 長音とハイフンは同じモールス符号 */
         (vec!['（'], "-.--.-"), /* 英文字の')'と同じモールス符号 */
         (vec!['）'], ".-..-."), /* 英文字の'"'と同じモールス符号 */
         (vec!['、'], ".-.-.-"), /* 英文字の'.'と同じモールス符号 */
@@ -173,8 +180,13 @@ pub fn set_translation_table() -> HashMap<char, &'static str> {
         (vec!['<'], "<"),
         (vec!['>'], ">"),
     ];
+}
+
+/// 文字 -> モールスコード変換テーブルを作成する
+pub fn set_translation_table() -> HashMap<char, &'static str> {
+    let mut table = HashMap::new();
 
-    for (x, v) in tbl {
+    for (x, v) in translation_entries() {
         for k in x {
             table.insert(k, v);
         }
@@ -0,0 +1,108 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use anyhow::{anyhow, Result};
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+
+use crate::{
+    keyer::{Keyer, Mark, Timeline},
+    sink::Sink,
+};
+
+/// WAV ファイルを読み込み、モノラル(-1.0 .. 1.0)のサンプルとサンプリング周波数を返す
+///
+/// 複数チャンネルの場合は平均をとる。
+pub fn read_wav<P: AsRef<Path>>(path: P) -> Result<(Vec<f32>, u32)> {
+    let mut reader = WavReader::open(path.as_ref()).map_err(|e| {
+        anyhow!(
+            "error: cannot read input file ({}): {}",
+            path.as_ref().display(),
+            e
+        )
+    })?;
+    let spec = reader.spec();
+
+    let samples: Vec<f32> = match spec.sample_format {
+        SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        SampleFormat::Int => {
+            let scale = (1_i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 / scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+    let channels = spec.channels.max(1) as usize;
+    let mono = samples
+        .chunks(channels)
+        .map(|c| c.iter().sum::<f32>() / channels as f32)
+        .collect();
+
+    return Ok((mono, spec.sample_rate));
+}
+
+/// モールス音を WAV ファイル(16bit PCM モノラル)に書き出す
+pub struct WavRenderer {
+    writer: WavWriter<BufWriter<File>>,
+    /// サンプリング周波数
+    sample_rate: u32,
+    /// タイムラインから波形を生成
+    keyer: Keyer,
+}
+
+impl WavRenderer {
+    /// 出力ファイルを作成する
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> Result<WavRenderer> {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let writer = WavWriter::create(path.as_ref(), spec).map_err(|e| {
+            anyhow!(
+                "error: cannot create output file ({}): {}",
+                path.as_ref().display(),
+                e
+            )
+        })?;
+
+        return Ok(WavRenderer {
+            writer,
+            sample_rate,
+            keyer: Keyer::new(sample_rate),
+        });
+    }
+
+    /// 確定したところまで波形にして書き出す。`all` なら最後まで
+    fn render(&mut self, all: bool, on_mark: &mut dyn FnMut(&Mark)) -> Result<()> {
+        let mut marks = Vec::new();
+
+        while let Some(value) = self.keyer.next_sample_within(all, &mut marks) {
+            self.writer.write_sample((value * i16::MAX as f32) as i16)?;
+        }
+        marks.iter().for_each(on_mark);
+
+        return Ok(());
+    }
+}
+
+impl Sink for WavRenderer {
+    fn sample_rate(&self) -> u32 {
+        return self.sample_rate;
+    }
+
+    /// タイムラインを波形にして書き出す
+    fn send(&mut self, timeline: Timeline, on_mark: &mut dyn FnMut(&Mark)) -> Result<()> {
+        self.keyer.push(timeline);
+
+        return self.render(false, on_mark);
+    }
+
+    /// 残りを書き出してヘッダを確定する
+    fn finish(&mut self, on_mark: &mut dyn FnMut(&Mark)) -> Result<()> {
+        self.render(true, on_mark)?;
+        self.writer.flush()?;
+
+        return Ok(());
+    }
+}
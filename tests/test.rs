@@ -1,4 +1,20 @@
 use assert_cmd::prelude::*; // Add methods on commands
+use clap::Parser;
+use morse_rs::{
+    args::Args,
+    decoder::{Alphabet, Decoder, UNKNOWN},
+    encoder::{Encoder, Symbols},
+    event::{Event, GapKind},
+    fading::{Fading, FadingGenerator},
+    morse::Morse,
+    noise::NoiseKind,
+    oscillator::Waveform,
+    player::{LineOption, Player},
+    random::player_seed,
+    sink::RecordingSink,
+    stream::{Heard, StreamDecoder},
+    timing::{Standard, Timing},
+};
 use predicates::prelude::*; // Used for writing assertions
 use std::process::Command; // Run programs
 
@@ -153,6 +169,36 @@ fn boundary_value_test_wpm() -> Result<(), Box<dyn std::error::Error>> {
         .assert()
         .failure();
 
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--wpm", "NaN"])
+        .args(&["CQ CQ"])
+        .assert()
+        .failure();
+    assert!(record(&[], "#! --wpm NaN\nE\n").is_err());
+
+    // 小数
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--wpm", "22.5"])
+        .args(&["CQ CQ"])
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--wpm", "2.9"])
+        .args(&["CQ CQ"])
+        .assert()
+        .failure();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--wpm", "60.1"])
+        .args(&["CQ CQ"])
+        .assert()
+        .failure();
+
     Ok(())
 }
 
@@ -310,3 +356,1525 @@ fn boundary_value_test_input() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+#[test]
+fn output_wav_file() -> Result<(), Box<dyn std::error::Error>> {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("output_wav_file.wav");
+    let _ = std::fs::remove_file(&path);
+
+    // 音声デバイスなしでファイルに書き出す
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["--output", path.to_str().unwrap()])
+        .args(&["--sample-rate", "8000"])
+        .args(&["--input", "tests/data/sample.txt"])
+        .assert()
+        .success();
+    assert!(predicate::path::is_file().eval(&path));
+
+    let reader = hound::WavReader::open(&path)?;
+    assert_eq!(reader.spec().sample_rate, 8000);
+    assert_eq!(reader.spec().channels, 1);
+    assert!(reader.duration() > 0);
+
+    // 拡張子が `.wav` 以外
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--output", "out.mp3"])
+        .args(&["CQ CQ"])
+        .assert()
+        .failure();
+
+    // サンプリング周波数の範囲外
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--output", "out.wav", "--sample-rate", "7999"])
+        .args(&["CQ CQ"])
+        .assert()
+        .failure();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--output", "out.wav", "--sample-rate", "192001"])
+        .args(&["CQ CQ"])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+fn output_wav_timing() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR"));
+
+    // (wpm, サンプリング周波数, 電文, 期待するサンプル数)
+    let cases = [
+        // 短点 60ms: 最後の符号の後には間隔を置かない
+        ("20", "8000", "E", 480),
+        // 短点 + 文字間隔(3短点) + 短点
+        ("20", "8000", "EE", 2400),
+        // 短点 25ms = 1102.5 サンプル: 9短点分で丸め誤差が蓄積しないこと
+        ("48", "44100", "5", 9922),
+    ];
+
+    for (i, (wpm, sample_rate, text, samples)) in cases.iter().enumerate() {
+        let path = dir.join(format!("output_wav_timing_{}.wav", i));
+
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+        cmd.args(&["--wpm", wpm, "--sample-rate", sample_rate])
+            .args(&["--output", path.to_str().unwrap()])
+            .arg(text)
+            .assert()
+            .success();
+
+        let reader = hound::WavReader::open(&path)?;
+        assert_eq!(reader.duration(), *samples, "{} at {} wpm", text, wpm);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn boundary_value_test_rise_time() -> Result<(), Box<dyn std::error::Error>> {
+    // 範囲内(単位は省略可)
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--rise-time", "5ms", "--envelope", "blackman"])
+        .args(&["CQ CQ"])
+        .assert()
+        .success();
+
+    // 境界上
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--rise-time", "0"])
+        .args(&["CQ CQ"])
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--rise-time", "50ms"])
+        .args(&["CQ CQ"])
+        .assert()
+        .success();
+
+    // 範囲外
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--rise-time", "51"])
+        .args(&["CQ CQ"])
+        .assert()
+        .failure();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--rise-time", "5s"])
+        .args(&["CQ CQ"])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+fn output_wav_envelope() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR"));
+
+    // 先頭 1ms の最大振幅を求める
+    let peak = |rise_time: &str| -> Result<i16, Box<dyn std::error::Error>> {
+        let path = dir.join(format!("output_wav_envelope_{}.wav", rise_time));
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+        cmd.args(&["--volume", "1", "--sample-rate", "8000"])
+            .args(&["--rise-time", rise_time])
+            .args(&["--output", path.to_str().unwrap()])
+            .arg("T")
+            .assert()
+            .success();
+
+        let mut reader = hound::WavReader::open(&path)?;
+        let samples = reader.samples::<i16>().take(8);
+        Ok(samples.map(|s| s.unwrap().abs()).max().unwrap())
+    };
+
+    // 立ち上がりなしでは即座に最大振幅近くまで達する
+    assert!(peak("0ms")? > i16::MAX / 2);
+    // 5ms かけて立ち上がる
+    assert!(peak("5ms")? < i16::MAX / 8);
+
+    // `#!` 行でも指定できる
+    let path = dir.join("output_wav_envelope_line.wav");
+    let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["--output", path.to_str().unwrap(), "--pipe"])
+        .write_stdin("#! --rise-time 8ms --envelope blackman --player A\nE\n#! --rise-time 0.1s\n")
+        .assert()
+        .failure();
+
+    let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["--output", path.to_str().unwrap(), "--pipe"])
+        .write_stdin("#! --rise-time 8ms --envelope blackman --player A\nE\n#! --player A\nE\n")
+        .assert()
+        .success();
+
+    Ok(())
+}
+
+#[test]
+fn output_wav_waveform() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR"));
+
+    // 最大振幅近くにあるサンプルの割合を求める
+    let ratio = |args: &[&str]| -> Result<f32, Box<dyn std::error::Error>> {
+        let path = dir.join(format!("output_wav_waveform_{}.wav", args.join("_")));
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+        cmd.args(&["--volume", "1", "--rise-time", "0", "--sample-rate", "8000"])
+            .args(args)
+            .args(&["--output", path.to_str().unwrap()])
+            .arg("T")
+            .assert()
+            .success();
+
+        let mut reader = hound::WavReader::open(&path)?;
+        let samples: Vec<i16> = reader.samples::<i16>().map(|s| s.unwrap()).collect();
+        let high = samples.iter().filter(|s| s.abs() > i16::MAX / 10 * 9);
+        let sounding = samples.iter().filter(|s| **s != 0);
+        Ok(high.count() as f32 / sounding.count() as f32)
+    };
+
+    // 矩形波はほぼ全体が最大振幅、正弦波や三角波は一部のみ
+    assert!(ratio(&["--waveform", "square"])? > 0.9);
+    assert!(ratio(&["--waveform", "sine"])? < 0.5);
+    assert!(ratio(&["--waveform", "triangle"])? < 0.5);
+    // 倍音を加えると、振幅が 1 を超えないよう全体を縮める
+    assert!(ratio(&["--waveform", "square", "--harmonics", "0.5"])? < 0.9);
+
+    // 倍音の指定
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--waveform", "sawtooth-bl", "--harmonics", "0.3,0,0.1"])
+        .args(&["CQ CQ"])
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--harmonics", "1.1"])
+        .args(&["CQ CQ"])
+        .assert()
+        .failure();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--harmonics", "0,0,0,0,0,0,0,0,0"])
+        .args(&["CQ CQ"])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+fn output_wav_noise() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR"));
+
+    let render = |name: &str, args: &[&str]| -> Result<Vec<i16>, Box<dyn std::error::Error>> {
+        let path = dir.join(format!("output_wav_noise_{}.wav", name));
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+        cmd.args(&["--volume", "1", "--sample-rate", "8000"])
+            .args(args)
+            .args(&["--output", path.to_str().unwrap()])
+            .arg("E E")
+            .assert()
+            .success();
+
+        let mut reader = hound::WavReader::open(&path)?;
+        Ok(reader.samples::<i16>().map(|s| s.unwrap()).collect())
+    };
+
+    // 語間(先頭の短点 + 文字内間隔の後)の実効値
+    let rms = |samples: &[i16]| -> f32 {
+        let gap = &samples[600..1200];
+        let sum: f32 = gap
+            .iter()
+            .map(|s| (*s as f32 / i16::MAX as f32).powi(2))
+            .sum();
+        (sum / gap.len() as f32).sqrt()
+    };
+
+    // 雑音なしなら語間は無音
+    assert_eq!(rms(&render("none", &[])?), 0.0);
+
+    // SN 比 10dB: 振幅 1 の正弦波の電力 0.5 に対して、雑音の実効値は約 0.22
+    for kind in ["white", "pink", "band"] {
+        let samples = render(kind, &["--snr", "10", "--noise", kind, "--seed", "1"])?;
+        let level = rms(&samples);
+        assert!(0.15 < level && level < 0.3, "{}: {}", kind, level);
+    }
+
+    // 同じシードなら同じ雑音
+    let a = render("seed_a", &["--snr", "0", "--seed", "7"])?;
+    let b = render("seed_b", &["--snr", "0", "--seed", "7"])?;
+    let c = render("seed_c", &["--snr", "0", "--seed", "8"])?;
+    assert_eq!(a, b);
+    assert_ne!(a, c);
+
+    // 範囲外
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--snr", "-21"])
+        .args(&["CQ CQ"])
+        .assert()
+        .failure();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--snr", "-20", "--noise", "pink"])
+        .args(&["CQ CQ"])
+        .assert()
+        .success();
+
+    Ok(())
+}
+
+#[test]
+fn qsb_fading() -> Result<(), Box<dyn std::error::Error>> {
+    let generator = FadingGenerator::new(1);
+
+    // 周期的なフェージングは、最大の強さから始まり半周期で最も弱くなる
+    assert_eq!(generator.gain(Fading::Sine, 0.8, 10.0, 0.0), 1.0);
+    assert!((generator.gain(Fading::Sine, 0.8, 10.0, 5.0) - 0.2).abs() < 1e-6);
+    assert_eq!(generator.gain(Fading::None, 0.8, 10.0, 5.0), 1.0);
+
+    // レイリー・フェージングは不規則に強弱し、1 を超える分は頭打ちになる
+    // (二乗平均は 1 - 1/e = 0.63 程度)
+    let gains: Vec<f32> = (0..10000)
+        .map(|i| generator.gain(Fading::Rayleigh, 1.0, 2.0, i as f64 * 0.05))
+        .collect();
+    let mean_square = gains.iter().map(|g| g * g).sum::<f32>() / gains.len() as f32;
+    assert!(0.5 < mean_square && mean_square < 0.75, "{}", mean_square);
+    assert!(gains.iter().any(|g| *g < 0.1));
+    for depth in [0.3, 0.5, 1.0] {
+        let max = (0..10000)
+            .map(|i| generator.gain(Fading::Rayleigh, depth, 2.0, i as f64 * 0.05))
+            .fold(0.0, f32::max);
+        assert_eq!(max, 1.0, "depth {}", depth);
+    }
+
+    // 同じシードなら同じフェージング
+    let other = FadingGenerator::new(1);
+    assert_eq!(
+        generator.gain(Fading::Rayleigh, 1.0, 2.0, 3.3),
+        other.gain(Fading::Rayleigh, 1.0, 2.0, 3.3)
+    );
+
+    // プレイヤー毎に指定できる
+    let sink = record(
+        &[],
+        "CQ\n#! --qsb rayleigh --qsb-depth 0.9 --qsb-period 4 --player DX\nK\n#! --player default\nK\n",
+    )?;
+    let qsb: Vec<(Fading, f32, f32)> = sink
+        .timelines()
+        .iter()
+        .map(|t| {
+            let c = t.condition();
+            (c.qsb, c.qsb_depth, c.qsb_period)
+        })
+        .collect();
+    assert_eq!(
+        qsb,
+        [
+            (Fading::None, 0.5, 8.0),
+            (Fading::Rayleigh, 0.9, 4.0),
+            (Fading::None, 0.5, 8.0),
+        ]
+    );
+
+    // 範囲外
+    assert!(record(&[], "#! --qsb-depth 1.5 --player DX\nK\n").is_err());
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--qsb", "sine", "--qsb-period", "0.4"])
+        .args(&["CQ CQ"])
+        .assert()
+        .failure();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--qsb", "sine", "--qsb-depth", "1", "--qsb-period", "120"])
+        .args(&["CQ CQ"])
+        .assert()
+        .success();
+
+    Ok(())
+}
+
+#[test]
+fn output_wav_mixing() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR"));
+
+    let render = |name: &str, text: &str| -> Result<Vec<i16>, Box<dyn std::error::Error>> {
+        let path = dir.join(format!("output_wav_mixing_{}.wav", name));
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+        cmd.args(&["--wpm", "20", "--sample-rate", "8000"])
+            .args(&["--output", path.to_str().unwrap(), "--pipe"])
+            .write_stdin(text)
+            .assert()
+            .success();
+
+        let mut reader = hound::WavReader::open(&path)?;
+        Ok(reader.samples::<i16>().map(|s| s.unwrap()).collect())
+    };
+
+    // 20 wpm: 短点 60ms = 480 サンプル。T は 3短点分
+    // 順に発音: T + 語間(7短点) + T
+    let sequential = render("sequential", "T\nT\n")?;
+    assert_eq!(sequential.len(), (3 + 7 + 3) * 480);
+
+    // 1短点後から重ねて発音: 全体は 1短点 + T
+    let text = "T\n#! --frequency 800 --player QRM --offset 0.06\nT\n";
+    let mixed = render("mixed", text)?;
+    assert_eq!(mixed.len(), (1 + 3) * 480);
+
+    // 重なっている区間は二局分の信号を足し合わせる
+    let peak = |samples: &[i16]| samples.iter().map(|s| s.abs()).max().unwrap();
+    assert!(peak(&mixed[480..1440]) > peak(&sequential[480..1440]) * 3 / 2);
+
+    // 重ねた行の後は、すべて終わってから語間を空けて発音する
+    let text = "T\n#! --player QRM --frequency 800 --offset 0\nE\n#! --player default\nT\n";
+    assert_eq!(render("after", text)?.len(), (3 + 7 + 3) * 480);
+
+    // 開始位置は負にできない
+    let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["--output", dir.join("x.wav").to_str().unwrap(), "--pipe"])
+        .write_stdin("T\n#! --offset -1\nT\n")
+        .assert()
+        .failure();
+
+    // 重ねる行の開始位置を記録する
+    let sink = record(&[], "CQ\n#! --player A --wpm 30 --offset 0.5\nK\nK\n")?;
+    let offsets: Vec<Option<u64>> = sink.timelines().iter().map(|t| t.offset()).collect();
+    assert_eq!(offsets, [None, Some(500), None]);
+
+    Ok(())
+}
+
+#[test]
+fn output_wav_impairments() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR"));
+
+    let render = |name: &str, args: &[&str]| -> Result<Vec<i16>, Box<dyn std::error::Error>> {
+        let path = dir.join(format!("output_wav_impairments_{}.wav", name));
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+        cmd.args(&["--wpm", "20", "--rise-time", "0", "--sample-rate", "8000"])
+            .args(args)
+            .args(&["--output", path.to_str().unwrap()])
+            .arg("T T")
+            .assert()
+            .success();
+
+        let mut reader = hound::WavReader::open(&path)?;
+        Ok(reader.samples::<i16>().map(|s| s.unwrap()).collect())
+    };
+
+    // 区間内の周波数を、ゼロ交差の数から求める
+    let frequency = |samples: &[i16]| -> f32 {
+        let crossings = samples
+            .windows(2)
+            .filter(|w| (w[0] < 0) != (w[1] < 0))
+            .count();
+        crossings as f32 / 2.0 / (samples.len() as f32 / 8000.0)
+    };
+
+    // 20 wpm: 短点 480 サンプル。語間(7短点)を挟んで、二つ目の T は 10短点目から
+    let clean = render("clean", &[])?;
+    assert!((frequency(&clean[0..1440]) - 600.0).abs() < 10.0);
+    assert!((frequency(&clean[4800..6240]) - 600.0).abs() < 10.0);
+
+    // ドリフト: 行の終わりに向けて周波数が上がる
+    let drift = render("drift", &["--drift", "300"])?;
+    assert!((frequency(&drift[0..1440]) - 600.0).abs() < 40.0);
+    assert!(frequency(&drift[4800..6240]) > 800.0);
+
+    // チャープ: キーを押した直後だけ周波数がずれる
+    let chirp = render("chirp", &["--chirp", "-200"])?;
+    assert!(frequency(&chirp[0..80]) < 500.0);
+    assert!((frequency(&chirp[400..1440]) - 600.0).abs() < 10.0);
+
+    // ハム: 振幅が電源のリプルで揺れる(50Hz の半周期 = 80 サンプルで最も小さい)
+    let hum = render("hum", &["--hum", "1", "--hum-frequency", "50"])?;
+    let peak = |samples: &[i16]| samples.iter().map(|s| s.abs()).max().unwrap();
+    assert!(peak(&hum[0..20]) > peak(&clean[0..20]) * 3 / 4);
+    assert!(peak(&hum[70..90]) < peak(&clean[70..90]) / 4);
+
+    // プレイヤー毎に指定できる
+    let sink = record(
+        &[],
+        "CQ\n#! --chirp 50 --drift -20 --hum 0.3 --player OLD\nK\n",
+    )?;
+    let tone = sink.timelines()[1].tone();
+    assert_eq!((tone.chirp, tone.drift, tone.hum), (50.0, -20.0, 0.3));
+    assert_eq!(sink.timelines()[0].tone().chirp, 0.0);
+
+    // 範囲外
+    assert!(record(&[], "#! --hum 2 --player OLD\nK\n").is_err());
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--chirp", "-201"])
+        .args(&["CQ CQ"])
+        .assert()
+        .failure();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--drift", "-300", "--hum", "0.5", "--hum-frequency", "120"])
+        .args(&["CQ CQ"])
+        .assert()
+        .success();
+
+    Ok(())
+}
+
+#[test]
+fn output_wav_filter() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR"));
+
+    // 600Hz の T の後に、900Hz の局の T
+    let render = |name: &str, args: &[&str]| -> Result<Vec<i16>, Box<dyn std::error::Error>> {
+        let path = dir.join(format!("output_wav_filter_{}.wav", name));
+        let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+        cmd.args(&["--wpm", "20", "--volume", "1", "--sample-rate", "8000"])
+            .args(args)
+            .args(&["--output", path.to_str().unwrap(), "--pipe"])
+            .write_stdin("T\n#! --player QRM --frequency 900\nT\n")
+            .assert()
+            .success();
+
+        let mut reader = hound::WavReader::open(&path)?;
+        Ok(reader.samples::<i16>().map(|s| s.unwrap()).collect())
+    };
+    let peak = |samples: &[i16]| samples.iter().map(|s| s.unsigned_abs()).max().unwrap() as f32;
+
+    // 20 wpm: 短点 480 サンプル。二つ目の T は 10短点目から
+    let first = 480..1440;
+    let second = 5280..6240;
+
+    // 600Hz を中心とする 200Hz 幅のフィルタでは、900Hz の局は聞こえなくなる
+    let filtered = render("narrow", &["--filter", "200"])?;
+    assert!(peak(&filtered[first.clone()]) > i16::MAX as f32 * 0.8);
+    assert!(peak(&filtered[second.clone()]) < i16::MAX as f32 * 0.1);
+
+    // BFO をずらすと逆になる
+    let shifted = render("shifted", &["--filter", "200", "--bfo-offset", "300"])?;
+    assert!(peak(&shifted[first.clone()]) < i16::MAX as f32 * 0.1);
+    assert!(peak(&shifted[second.clone()]) > i16::MAX as f32 * 0.8);
+
+    // キーを離した後もしばらく鳴り続ける(リンギング)
+    let clean = render("clean", &[])?;
+    assert_eq!(peak(&clean[1460..1500]), 0.0);
+    assert!(peak(&filtered[1460..1500]) > 0.0);
+
+    // 範囲外
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--filter", "40"])
+        .args(&["CQ CQ"])
+        .assert()
+        .failure();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--filter", "500", "--bfo-offset", "-550"])
+        .args(&["CQ CQ"])
+        .assert()
+        .failure();
+
+    // 通過域の上端(600 + 1500 + 3000 / 2 = 3600Hz)はサンプリング周波数の 0.45 倍未満
+    let args = [
+        "--filter",
+        "3000",
+        "--bfo-offset",
+        "1500",
+        "--output",
+        "out.wav",
+    ];
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&args)
+        .args(&["--sample-rate", "8000", "CQ CQ"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("passband"));
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&args)
+        .args(&["--sample-rate", "8001", "CQ CQ"])
+        .assert()
+        .success();
+
+    // `#!` 行でも確かめる
+    let args = [&args[..], &["--sample-rate", "8000"]].concat();
+    assert!(record(&args, "#! --player QRM --frequency 900\nT\n").is_err());
+
+    Ok(())
+}
+
+/// 電文をキーイングの記録用出力先へ送る
+fn record(args: &[&str], text: &str) -> Result<RecordingSink, Box<dyn std::error::Error>> {
+    let opt = Args::parse_from([&["morse-rs", "--pipe"], args].concat());
+    let morse = Morse::new(&opt);
+    let mut sink = RecordingSink::new(1000);
+
+    morse.perform(&mut text.as_bytes(), &mut sink)?;
+
+    Ok(sink)
+}
+
+#[test]
+fn recording_sink_player() -> Result<(), Box<dyn std::error::Error>> {
+    let sink = record(
+        &["--frequency", "600"],
+        "CQ\n#! --frequency 450 --wpm 20 --player JA7QRS\nK\n#! --player default\nK\n#! --player JA7QRS\nK\n",
+    )?;
+
+    // 行毎にプレイヤーの周波数で発音する
+    let frequencies: Vec<f32> = sink
+        .timelines()
+        .iter()
+        .map(|t| t.tone().frequency)
+        .collect();
+    assert_eq!(frequencies, [600.0, 450.0, 600.0, 450.0]);
+
+    // 波形と倍音もプレイヤー毎
+    let sink = record(
+        &["--waveform", "triangle"],
+        "CQ\n#! --waveform square-bl --harmonics 0.2,0.1 --player JA7QRS\nK\n#! --player default\nK\n",
+    )?;
+    let tones: Vec<(Waveform, Vec<f32>)> = sink
+        .timelines()
+        .iter()
+        .map(|t| (t.tone().waveform, t.tone().harmonics.clone()))
+        .collect();
+    assert_eq!(
+        tones,
+        [
+            (Waveform::Triangle, vec![]),
+            (Waveform::SquareBl, vec![0.2, 0.1]),
+            (Waveform::Triangle, vec![]),
+        ]
+    );
+    assert!(record(&[], "#! --harmonics 0.2,x --player A\nK\n").is_err());
+
+    // 雑音の強さもプレイヤー毎(シードはプレイヤー毎に変わる)
+    let sink = record(
+        &["--snr", "20", "--seed", "3"],
+        "CQ\n#! --snr -5 --noise pink --player DX\nK\n#! --player default\nK\n",
+    )?;
+    let conditions: Vec<(Option<f32>, NoiseKind, u64)> = sink
+        .timelines()
+        .iter()
+        .map(|t| (t.condition().snr, t.condition().noise, t.condition().seed))
+        .collect();
+    assert_eq!(
+        conditions,
+        [
+            (Some(20.0), NoiseKind::White, 3),
+            (Some(-5.0), NoiseKind::Pink, player_seed(3, "DX")),
+            (Some(20.0), NoiseKind::White, 3),
+        ]
+    );
+
+    // 未定義のプレイヤー
+    assert!(record(&[], "#! --player JA1XXX\nK\n").is_err());
+
+    // 選択肢は大文字・小文字を区別しない。重複指定や選択肢にない値はエラー
+    let o = LineOption::parse(" --qsb Rayleigh --level 初段")?;
+    assert_eq!((o.qsb, o.wpm), (Some(Fading::Rayleigh), Some(18.0)));
+    assert!(LineOption::parse(" --envelope cosine --envelope blackman").is_err());
+    assert!(LineOption::parse(" --wpm 20 --level 初段").is_err());
+    assert!(LineOption::parse(" --waveform ramp").is_err());
+
+    // `#!` 行の範囲チェックは、反映した後のプレイヤーの設定全体に対して行う
+    let opt = Args::parse_from([
+        "morse-rs",
+        "--pipe",
+        "--frequency",
+        "800",
+        "--filter",
+        "300",
+        "--bfo-offset",
+        "-100",
+        "--standard",
+        "codex",
+        "--qsb",
+        "sine",
+    ]);
+    let mut player = Player::new(&opt);
+    let probe = player.to_args();
+    assert_eq!(
+        (probe.filter, probe.standard, probe.qsb),
+        (Some(300.0), Standard::Codex, Fading::Sine)
+    );
+    assert_eq!(probe.frequency + probe.bfo_offset, 700.0);
+    // 音の周波数を変えても、フィルタの中心は受信機の設定のまま
+    player.apply(&LineOption::parse(" --frequency 500")?)?;
+    let probe = player.to_args();
+    assert_eq!(probe.frequency + probe.bfo_offset, 700.0);
+
+    Ok(())
+}
+
+#[test]
+fn recording_sink_fist() -> Result<(), Box<dyn std::error::Error>> {
+    // キーを押している/離している長さ[サンプル]の並び
+    let keying = |sink: &RecordingSink| -> Vec<(bool, u64)> {
+        sink.timelines()[0]
+            .elements()
+            .iter()
+            .map(|e| (e.key_down, e.samples))
+            .collect()
+    };
+
+    // 20 wpm: 短点 60ms = 60 サンプル
+    // 重み 60%: 短点は 72、続く間隔は 48。長点は 3.5 倍に重みの分を加える
+    let sink = record(
+        &["--wpm", "20", "--dah-ratio", "3.5", "--weight", "60"],
+        "A\n",
+    )?;
+    assert_eq!(keying(&sink), [(true, 72), (false, 48), (true, 222)]);
+
+    // バグキー: 長点だけが重くなり、揺らぎも長点だけに加わる
+    let args = [
+        "--wpm", "20", "--bug", "50", "--jitter", "20", "--seed", "1",
+    ];
+    let sink = record(&args, "5\n")?;
+    assert!(keying(&sink)
+        .iter()
+        .all(|e| *e == (true, 60) || *e == (false, 60)));
+    let sink = record(&args, "0\n")?;
+    assert!(keying(&sink)
+        .iter()
+        .any(|(down, samples)| *down && *samples != 210));
+
+    // 揺らぎは同じシードなら同じになる
+    let args = ["--wpm", "20", "--jitter", "10", "--seed", "5"];
+    let a = keying(&record(&args, "PARIS\n")?);
+    let b = keying(&record(&args, "PARIS\n")?);
+    assert_eq!(a, b);
+    assert!(a
+        .iter()
+        .any(|(_, samples)| ![60, 120, 180].contains(samples)));
+
+    // プレイヤー毎に指定できる(各行の先頭は語間、次が最初の短点)
+    let sink = record(
+        &["--wpm", "20"],
+        "E\n#! --weight 75 --player HEAVY\nE\n#! --player default\nE\n",
+    )?;
+    let dits: Vec<u64> = sink
+        .timelines()
+        .iter()
+        .skip(1)
+        .map(|t| t.elements()[1].samples)
+        .collect();
+    assert_eq!(dits, [90, 60]);
+    assert!(record(&[], "#! --weight 80 --player HEAVY\nK\n").is_err());
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--dah-ratio", "1.9"])
+        .args(&["CQ CQ"])
+        .assert()
+        .failure();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--dah-ratio", "3.3", "--weight", "25", "--jitter", "30"])
+        .args(&["--bug", "100", "CQ CQ"])
+        .assert()
+        .success();
+
+    Ok(())
+}
+
+#[test]
+fn recording_sink_timing() -> Result<(), Box<dyn std::error::Error>> {
+    // 各行の先頭の語間の長さ[サンプル]
+    let word_gaps = |sink: &RecordingSink| -> Vec<u64> {
+        sink.timelines()
+            .iter()
+            .skip(1)
+            .map(|t| t.elements()[0].samples)
+            .collect()
+    };
+    // 20 wpm: 短点 60 サンプル、語間 420 サンプル
+    let sink = record(&["--wpm", "20"], "E\nE\n")?;
+    assert_eq!(word_gaps(&sink), [420]);
+
+    // Farnsworth は文字間・語間、Wordsworth は語間だけを伸ばす
+    let sink = record(
+        &[
+            "--wpm",
+            "20",
+            "--farnsworth-timing",
+            "2",
+            "--wordsworth",
+            "1.5",
+        ],
+        "EE\nE\n",
+    )?;
+    assert_eq!(word_gaps(&sink), [1260]);
+    // 文字間は短点の後の間隔を含めて 3短点分(を 2 倍した 360)
+    assert_eq!(sink.timelines()[0].elements()[1].samples, 360);
+
+    // プレイヤー毎に指定できる
+    let sink = record(
+        &["--wpm", "20"],
+        "E\n#! --wordsworth 2 --player SLOW\nE\n#! --player default\nE\n",
+    )?;
+    assert_eq!(word_gaps(&sink), [840, 420]);
+
+    let timing = Timing {
+        wpm: 20.0,
+        effective_wpm: None,
+        standard: Standard::Paris,
+        wabun: 75.0,
+        dah_ratio: 3.3,
+        weight: 50.0,
+        farnsworth: 1.0,
+        wordsworth: 1.0,
+    };
+    let (down, up) = timing.element(true);
+    assert!((down - 0.198).abs() < 1e-9 && (up - 0.06).abs() < 1e-9);
+
+    // 小数の wpm: 22.5 wpm の短点は 53.3ms。CODEX では 60短点を 1 語とする
+    let sink = record(&["--wpm", "22.5"], "E\n")?;
+    assert_eq!(sink.timelines()[0].elements()[0].samples, 53);
+    let sink = record(&["--wpm", "20", "--standard", "codex"], "E\nE\n")?;
+    assert_eq!(sink.timelines()[0].elements()[0].samples, 50);
+    assert_eq!(word_gaps(&sink), [350]);
+
+    // 語間までを含めて、PARIS は 50短点、CODEX は 60短点で 1 語(最後の語の後には語間がない)
+    let sink = record(&["--wpm", "20"], "PARIS PARIS\n")?;
+    assert_eq!(sink.timelines()[0].len(), (50 + 50 - 7) * 60);
+    let sink = record(&["--wpm", "20", "--standard", "codex"], "CODEX CODEX\n")?;
+    assert_eq!(sink.timelines()[0].len(), (60 + 60 - 7) * 50);
+    let sink = record(&["--wpm", "20"], "#! --wpm 12.5 --standard codex\nE\n")?;
+    assert_eq!(sink.timelines()[0].elements()[0].samples, 80);
+
+    // ARRL の Farnsworth: 文字は 18 wpm、全体で 10 wpm
+    // 文字間・語間の単位は (60 * 18 - 37.2 * 10) / (10 * 18) / 19 = 0.207 秒
+    let sink = record(&["--char-wpm", "18", "--effective-wpm", "10"], "EE\nE\n")?;
+    assert_eq!(word_gaps(&sink), [1449]);
+    // 文字間は短点の後の間隔を含めて 3 単位分で 0.621 秒
+    assert_eq!(sink.timelines()[0].elements()[1].samples, 621);
+
+    // 実効速度が文字の速度と同じなら、標準の長さ
+    let sink = record(&["--wpm", "20", "--effective-wpm", "20"], "E\nE\n")?;
+    assert_eq!(word_gaps(&sink), [420]);
+
+    // `#!` 行でプレイヤー毎に指定できる
+    let sink = record(
+        &["--wpm", "20"],
+        "E\n#! --char-wpm 18 --effective-wpm 10 --player ARRL\nE\n#! --player default\nE\n",
+    )?;
+    assert_eq!(word_gaps(&sink), [1449, 420]);
+    assert!(record(&["--wpm", "20"], "#! --effective-wpm 25\nE\n").is_err());
+    // 文字の速度を実効速度より下げることもできない
+    assert!(record(&["--wpm", "20", "--effective-wpm", "10"], "#! --wpm 8\nE\n").is_err());
+
+    // 語全体でも実効速度になる: 10 wpm では PARIS(語間を含む)が 6 秒なので、二つ目の P は 6 秒から
+    let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["--wpm", "18", "--effective-wpm", "10"])
+        .args(&["encode", "--json", "PARIS PARIS"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            r#"{"char":"P","code":".--.","start":6.000}"#,
+        ));
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--wordsworth", "0.9", "CQ CQ"])
+        .assert()
+        .failure();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--wpm", "18", "--effective-wpm", "20", "CQ CQ"])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+fn recording_sink_speed_units() -> Result<(), Box<dyn std::error::Error>> {
+    // 最初の短点(または長点)の長さ[サンプル]
+    let first = |sink: &RecordingSink| -> Vec<u64> {
+        sink.timelines()
+            .iter()
+            .map(|t| t.elements().iter().find(|e| e.key_down).unwrap().samples)
+            .collect()
+    };
+
+    // 100 CPM = 20 wpm: 短点 60 サンプル
+    assert_eq!(first(&record(&["--cpm", "100"], "E\n")?), [60]);
+    // 初段 90 CPM = 18 wpm: 短点 66.7 サンプル
+    assert_eq!(first(&record(&["--level", "初段"], "E\n")?), [67]);
+    assert_eq!(first(&record(&["--level", "shodan"], "E\n")?), [67]);
+    assert_eq!(
+        first(&record(&[], "#! --level 3級\nE\n#! --cpm 100\nE\n")?),
+        [240, 60]
+    );
+
+    // 和文の行は速度の 75% で送る(20 wpm -> 15 wpm: 短点 80 サンプル)
+    assert_eq!(
+        first(&record(&["--wpm", "20"], "ホ\nE\nほ\n")?),
+        [240, 60, 240]
+    );
+    assert_eq!(
+        first(&record(&["--wpm", "20", "--wabun-ratio", "100"], "ホ\n")?),
+        [180]
+    );
+    assert_eq!(
+        first(&record(&["--wpm", "20"], "#! --wabun-ratio 50\nホ\n")?),
+        [360]
+    );
+    // 速度の下限でも和文は遅くする(3 wpm -> 2.25 wpm: 長点 1600 サンプル)
+    assert_eq!(first(&record(&["--wpm", "3"], "ホ\nE\n")?), [1600, 400]);
+    let args = ["--wpm", "20", "--ramp-to", "3", "--ramp-over", "1"];
+    assert_eq!(first(&record(&args, "E\nホ\n")?), [60, 1600]);
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--wpm", "20", "--cpm", "100", "CQ CQ"])
+        .assert()
+        .failure();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--level", "10級", "CQ CQ"])
+        .assert()
+        .failure();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--cpm", "301", "CQ CQ"])
+        .assert()
+        .failure();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--wabun-ratio", "49", "CQ CQ"])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+fn recording_sink_ramp() -> Result<(), Box<dyn std::error::Error>> {
+    // キーを押している長さ[サンプル]の並び
+    let downs = |sink: &RecordingSink| -> Vec<u64> {
+        sink.timelines()
+            .iter()
+            .flat_map(|t| t.elements().iter().filter(|e| e.key_down))
+            .map(|e| e.samples)
+            .collect()
+    };
+
+    // 行毎: 10 wpm から 2 行かけて 20 wpm へ(短点 120 -> 80 -> 60)
+    let args = ["--wpm", "10", "--ramp-to", "20", "--ramp-over", "2"];
+    assert_eq!(downs(&record(&args, "E\nE\nE\nE\n")?), [120, 80, 60, 60]);
+
+    // 文字毎: 一行の中でも速度が変わる
+    let args = [&args[..], &["--ramp-by", "char"]].concat();
+    assert_eq!(downs(&record(&args, "EEEE\n")?), [120, 80, 60, 60]);
+
+    // 時間毎: 20 wpm から 0.3 秒かけて 10 wpm へ
+    // 最初の E で 0.06 秒進み、速度は 0.9 倍の 18 wpm(短点 67)
+    let args = ["--wpm", "20", "--ramp-to", "10"];
+    let args = [&args[..], &["--ramp-over", "0.3", "--ramp-by", "time"]].concat();
+    assert_eq!(downs(&record(&args, "EE\n")?), [60, 67]);
+
+    // プレイヤーの速度には同じ倍率を掛ける
+    let args = ["--wpm", "10", "--ramp-to", "20", "--ramp-over", "1"];
+    assert_eq!(
+        downs(&record(&args, "E\n#! --wpm 20 --player B\nE\n")?),
+        [120, 30]
+    );
+
+    // 倍率を掛けても 60 wpm(短点 20)より速くはならない
+    let args = ["--wpm", "5", "--ramp-to", "60", "--ramp-over", "1"];
+    assert_eq!(
+        downs(&record(&args, "E\n#! --wpm 60 --player B\nE\n")?),
+        [240, 20]
+    );
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--ramp-to", "30", "CQ CQ"])
+        .assert()
+        .failure();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--ramp-to", "30", "--ramp-over", "0", "CQ CQ"])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+fn events_iterator() -> Result<(), Box<dyn std::error::Error>> {
+    // 時間を μs 単位に丸める
+    let round = |event: Event| -> Event {
+        let round = |d: f64| (d * 1e6).round() / 1e6;
+        match event {
+            Event::ToneOn { duration } => Event::ToneOn {
+                duration: round(duration),
+            },
+            Event::Gap { kind, duration } => Event::Gap {
+                kind,
+                duration: round(duration),
+            },
+            event => event,
+        }
+    };
+
+    let opt = Args::parse_from(["morse-rs", "--pipe", "--wpm", "20"]);
+    let morse = Morse::new(&opt);
+    let text = "EI\n#! --player B --wpm 10\n<SK>\n";
+    let events = morse
+        .events(text.as_bytes())
+        .map(|e| e.map(round))
+        .collect::<anyhow::Result<Vec<Event>>>()?;
+
+    let tone = |duration| Event::ToneOn { duration };
+    let gap = |kind, duration| Event::Gap { kind, duration };
+    assert_eq!(
+        events,
+        [
+            Event::LineStart {
+                text: "EI".to_string(),
+                offset: None,
+                wpm: 20.0
+            },
+            Event::CharStart('E'),
+            tone(0.06),
+            gap(GapKind::Letter, 0.18),
+            Event::CharStart('I'),
+            tone(0.06),
+            gap(GapKind::Element, 0.06),
+            tone(0.06),
+            Event::LineEnd,
+            Event::PlayerChange("B".to_string()),
+            Event::LineOption(Box::new(LineOption::parse(" --player B --wpm 10")?)),
+            // 前の行の最後の符号の後の間隔を含めて、新しい速度で 7短点
+            gap(GapKind::Word, 0.84),
+            Event::LineStart {
+                text: "<SK>".to_string(),
+                offset: None,
+                wpm: 10.0
+            },
+            // 一文字化した文字の間には文字間が入らない
+            Event::CharStart('<'),
+            Event::CharStart('S'),
+            tone(0.12),
+            gap(GapKind::Element, 0.12),
+            tone(0.12),
+            gap(GapKind::Element, 0.12),
+            tone(0.12),
+            gap(GapKind::Element, 0.12),
+            Event::CharStart('K'),
+            tone(0.36),
+            gap(GapKind::Element, 0.12),
+            tone(0.12),
+            gap(GapKind::Element, 0.12),
+            tone(0.36),
+            Event::CharStart('>'),
+            Event::LineEnd,
+        ]
+    );
+
+    // 重ねて送る行は、語間の代わりに開始位置を持つ
+    let text = "E\n#! --offset 0.5\nE\n";
+    let events: Vec<Event> = morse
+        .events(text.as_bytes())
+        .collect::<anyhow::Result<_>>()?;
+    assert!(events.contains(&Event::LineStart {
+        text: "E".to_string(),
+        offset: Some(0.5),
+        wpm: 20.0
+    }));
+    assert!(!events.iter().any(|e| matches!(
+        e,
+        Event::Gap {
+            kind: GapKind::Word,
+            ..
+        }
+    )));
+
+    // 解釈できない行でエラーになり、その後は何も返さない
+    let mut events = morse.events("E\n#! --bogus\nE\n".as_bytes());
+    assert!(events.by_ref().take(4).all(|e| e.is_ok()));
+    assert!(events.next().unwrap().is_err());
+    assert!(events.next().is_none());
+
+    Ok(())
+}
+
+#[test]
+fn decode_notation() -> Result<(), Box<dyn std::error::Error>> {
+    let decoder = Decoder::new(Alphabet::Latin, " ", "/");
+    let decoded = decoder.decode("-.-. --.- / -.. . / .--- .- .---- ...");
+    assert_eq!(decoded.text, "CQ DE JA1S");
+    assert!(decoded.ambiguous.is_empty() && decoded.unknown.is_empty());
+
+    // 同じ符号の文字と、復号できない符号を知らせる
+    let decoded = decoder.decode(".-..-. ...-.- ...-.-");
+    assert_eq!(decoded.text, format!("\"{}{}", UNKNOWN, UNKNOWN));
+    assert_eq!(
+        decoded.ambiguous,
+        [(".-..-.".to_string(), vec!['"', '«', '»'])]
+    );
+    assert_eq!(decoded.unknown, ["...-.-"]);
+
+    // 区切りや短点・長点の表記は変えられる
+    let decoder = Decoder::new(Alphabet::Latin, "|", "  ");
+    assert_eq!(decoder.decode("·−|−···  −·−·").text, "AB C");
+
+    // 和文では同じ符号が別の文字になり、濁点・半濁点は前の文字と合わせる
+    let decoder = Decoder::new(Alphabet::Wabun, " ", "/");
+    assert_eq!(decoder.decode(".- .-.. .. -... ..--.").text, "イガパ");
+    assert_eq!(decoder.lookup(".-"), Some(&['イ'][..]));
+    assert_eq!(decoder.lookup("--"), Some(&['ヨ', 'ョ'][..]));
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["decode", "-.-. --.- / -.. ."])
+        .assert()
+        .success()
+        .stdout("CQ DE\n");
+
+    let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["decode", "--alphabet", "wabun"])
+        .write_stdin(".-\n...-.-\n")
+        .assert()
+        .success()
+        .stdout(format!("イ\n{}\n", UNKNOWN))
+        .stderr(predicate::str::contains("`...-.-` is unknown"));
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["decode", "--word-separator", " ", ".-"])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+fn decode_wav() -> Result<(), Box<dyn std::error::Error>> {
+    // 途中で速度が変わっても、短点の長さを推定し直して読み取る
+    let decoder = Decoder::new(Alphabet::Latin, " ", "/");
+    let mut keying = vec![];
+    for (dit, code) in [
+        (0.06, "-.-. --.-"),
+        (0.035, "-.- ..."),
+        (0.08, "...- --- .."),
+    ] {
+        for (k, letter) in code.split(' ').enumerate() {
+            if 0 < k {
+                keying.push((false, 3.0 * dit));
+            }
+            for (n, c) in letter.chars().enumerate() {
+                if 0 < n {
+                    keying.push((false, dit));
+                }
+                keying.push((true, if c == '-' { 3.0 * dit } else { dit }));
+            }
+        }
+        keying.push((false, 7.0 * dit));
+    }
+    assert_eq!(decoder.decode_keying(&keying).text, "CQ KS VOI");
+
+    // 書き出した録音を読み取る
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let path = dir.join("decode_wav.wav");
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["--sample-rate", "8000", "--frequency", "650"])
+        .args(&["--output", path.to_str().unwrap()])
+        .arg("CQ DE JA1ABC\n#! --wpm 35\nUR 599\n#! --wpm 15\nTU")
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["decode", "--wav", path.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout("CQ DE JA1ABC UR 599 TU\n");
+
+    // 和文の濁点・半濁点は前の文字と合わせる
+    let path = dir.join("decode_wav_wabun.wav");
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["--sample-rate", "8000", "--wpm", "25"])
+        .args(&["--output", path.to_str().unwrap()])
+        .arg("イロハ ガパ")
+        .assert()
+        .success();
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["decode", "--alphabet", "wabun", "--tone", "600"])
+        .args(&["--wav", path.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout("イロハ ガパ\n");
+
+    // 音の周波数は録音から探すので、--wav と併用する
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["decode", "--tone", "600", ".-"])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+fn skim_recording() -> Result<(), Box<dyn std::error::Error>> {
+    // 重なった三局を、それぞれの周波数で復号する
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("skim_recording.wav");
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["--sample-rate", "8000", "--input", "tests/data/pileup.txt"])
+        .args(&["--output", path.to_str().unwrap()])
+        .assert()
+        .success();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["skim", path.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(concat!(
+            "00:00.0    600 Hz  CQ TEST DE JA1ABC\n",
+            "00:00.5    800 Hz  JA1ABC DE JH2XYZ\n",
+            "00:01.5    680 Hz  TEST JR3QQQ\n",
+        ));
+
+    // 探す範囲を狭めると、その中の信号だけ
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["skim", "--low", "700", "--high", "1000"])
+        .arg(path.to_str().unwrap())
+        .assert()
+        .success()
+        .stdout("00:00.5    800 Hz  JA1ABC DE JH2XYZ\n");
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["skim", "--low", "1000", "--high", "700"])
+        .arg(path.to_str().unwrap())
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+fn decode_stdin() -> Result<(), Box<dyn std::error::Error>> {
+    // 生 PCM を受信しながら復号し、長い無音で行を改める
+    let raw = Command::cargo_bin(env!("CARGO_PKG_NAME"))?
+        .args(&["--raw", "s16", "--sample-rate", "8000"])
+        .arg("CQ DE JA1ABC\n#! --offset 12\nJH2XYZ")
+        .output()?
+        .stdout;
+    let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["decode", "--stdin", "--sample-rate", "8000"])
+        .write_stdin(raw)
+        .assert()
+        .success()
+        // 速度は推定なので ±1 wpm の幅を許す
+        .stdout(predicate::str::is_match(
+            r"^\[2[456] wpm\] CQ DE JA1ABC\n\[2[456] wpm\] JH2XYZ\n$",
+        )?);
+
+    // 文字は入力の終わりを待たずに返し、濁点と合わさる文字は次の文字まで保留する
+    let raw = Command::cargo_bin(env!("CARGO_PKG_NAME"))?
+        .args(&["--raw", "s16", "--sample-rate", "8000", "イロハ ガパ"])
+        .output()?
+        .stdout;
+    let decoder = Decoder::new(Alphabet::Wabun, " ", "/");
+    let mut stream = StreamDecoder::new(decoder, 8000, Some(600.0));
+    // 何も聞いていなければ速度は分からない(`[0 wpm]` のようには表示しない)
+    assert_eq!(stream.wpm(), None);
+    let mut heard = vec![];
+    for x in raw.chunks_exact(2) {
+        heard.extend(stream.push(i16::from_le_bytes([x[0], x[1]]) as f32 / i16::MAX as f32));
+    }
+    assert!(heard.len() > 2, "{:?}", heard);
+    heard.extend(stream.finish());
+
+    let texts: Vec<String> = heard
+        .iter()
+        .filter_map(|h| match h {
+            Heard::Text(t) => Some(t.clone()),
+            Heard::Break => None,
+        })
+        .collect();
+    assert_eq!(texts.concat(), "イロハ ガパ");
+    assert!(!texts.iter().any(|t| t.ends_with('カ')), "{:?}", texts);
+    assert_eq!(heard.last(), Some(&Heard::Break));
+    let wpm = stream.wpm().unwrap();
+    assert!((18.0..20.0).contains(&wpm), "{}", wpm);
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["decode", "--stdin", "--sample-rate", "4000"])
+        .assert()
+        .failure();
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["decode", "--stdin", "-.-."]).assert().failure();
+
+    Ok(())
+}
+
+#[test]
+fn encode_text() -> Result<(), Box<dyn std::error::Error>> {
+    // 発音せずに符号を出力する。`<...>` は一文字にし、復号すると元に戻る
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["encode", "CQ DE JA1ZZZ <BT>"])
+        .assert()
+        .success()
+        .stdout("-.-. --.- / -.. . / .--- .- .---- --.. --.. --.. / -...-\n");
+    let decoder = Decoder::new(Alphabet::Latin, " ", "/");
+    assert_eq!(decoder.decode("-.-. --.- / -...-").text, "CQ =");
+
+    // `<...>` の前後の文字とは、文字間で区切る
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["encode", "A<SOS> K<AR> <SOS>A"])
+        .assert()
+        .success()
+        .stdout(".- ...---... / -.- .-.-. / ...---... .-\n");
+
+    // 記号や読み方、区切りは変えられる。二文字分の符号は文字間の区切りでつなぐ
+    let opt = Args::parse_from(["morse-rs", "--pipe"]);
+    let morse = Morse::new(&opt);
+    let marks = Symbols::Marks {
+        dot: "·".to_string(),
+        dash: "−".to_string(),
+    };
+    let encoder = Encoder::new(marks, "|", "  ");
+    let encoded: Vec<String> = encoder
+        .encode(morse.events("SOS & ガ".as_bytes()))
+        .map(|e| e.unwrap().code)
+        .collect();
+    assert_eq!(encoded, ["···|−−−|···  ·|···  ·−··|··"]);
+    let encoder = Encoder::new(Symbols::Phonetic, " ", " / ");
+    assert_eq!(encoder.code('C').unwrap(), "dah-di-dah-dit");
+    assert_eq!(encoder.code('E').unwrap(), "dit");
+    assert_eq!(encoder.code(' '), None);
+
+    // `#!` 行を解釈し、行毎の速度や重ねた行の開始時刻を JSON で出力する
+    let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["--wpm", "12", "encode", "--json"])
+        .write_stdin("E\"\n#! --player fast --wpm 24 --offset 0.5\nT\n")
+        .assert()
+        .success()
+        .stdout(concat!(
+            r#"{"text":"E\"","player":"default","wpm":12,"start":0.000,"duration":1.900,"code":". .-..-.","letters":[{"char":"E","code":".","start":0.000},{"char":"\"","code":".-..-.","start":0.400}]}"#,
+            "\n",
+            r#"{"text":"T","player":"fast","wpm":24,"start":0.500,"duration":0.150,"code":"-","letters":[{"char":"T","code":"-","start":0.500}]}"#,
+            "\n"
+        ));
+
+    // 速度は行の送り始めのもので、和文の割合やランプの倍率を反映する
+    let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    let output = cmd
+        .args(&["--wpm", "10", "--ramp-to", "20", "--ramp-over", "2"])
+        .args(&["encode", "--json"])
+        .write_stdin("E\nE\nホ\n")
+        .output()?;
+    let wpms: Vec<&str> = std::str::from_utf8(&output.stdout)?
+        .lines()
+        .map(|l| l.split(',').nth(2).unwrap())
+        .collect();
+    assert_eq!(wpms, [r#""wpm":10"#, r#""wpm":15"#, r#""wpm":15"#]);
+
+    // 20 wpm: PARIS は語間を含めて 50短点 = 3 秒なので、二つ目の P は 3 秒から
+    let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["--wpm", "20", "encode", "--json", "PARIS PARIS"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            r#"{"char":"P","code":".--.","start":3.000}"#,
+        ));
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["encode", "--phonetic", "--dot", "*", "CQ"])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+fn recording_sink_prosign() -> Result<(), Box<dyn std::error::Error>> {
+    // 20 wpm: 短点 60ms = 60 サンプル
+    let sink = record(&["--wpm", "20"], "BT\n<BT>\n")?;
+    let timelines = sink.timelines();
+
+    // B(9短点) + 文字間(3短点) + T(3短点)
+    assert_eq!(timelines[0].len(), 15 * 60);
+    // 語間(7短点) + 符号間(1短点)でつないだ B T
+    assert_eq!(timelines[1].len(), (7 + 13) * 60);
+
+    // キーイングは、- . . . - の順
+    let keys: Vec<u64> = timelines[1]
+        .elements()
+        .iter()
+        .filter(|e| e.key_down)
+        .map(|e| e.samples / 60)
+        .collect();
+    assert_eq!(keys, [3, 1, 1, 1, 3]);
+
+    // 前の文字との間は文字間のまま: K(9短点) + 文字間(3短点) + AR(13短点)
+    let sink = record(&["--wpm", "20"], "K<AR>\n")?;
+    assert_eq!(sink.timelines()[0].len(), (9 + 3 + 13) * 60);
+
+    Ok(())
+}
+
+#[test]
+fn output_raw_pcm() -> Result<(), Box<dyn std::error::Error>> {
+    // 20 wpm, 8000Hz: E = 短点 = 480 サンプル
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    let output = cmd
+        .args(&["--wpm", "20", "--sample-rate", "8000", "--raw", "s16", "E"])
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout.len(), 480 * 2);
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    let output = cmd
+        .args(&["--wpm", "20", "--sample-rate", "8000", "--raw", "f32", "E"])
+        .output()?;
+    assert!(output.status.success());
+    assert_eq!(output.stdout.len(), 480 * 4);
+
+    // `#!` 行のプレイヤー切り替えも反映される
+    let mut cmd = assert_cmd::Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    let output = cmd
+        .args(&[
+            "--wpm",
+            "20",
+            "--sample-rate",
+            "8000",
+            "--raw",
+            "s16",
+            "--pipe",
+        ])
+        .write_stdin("#! --wpm 10 --player A\nE\n")
+        .output()?;
+    assert_eq!(output.stdout.len(), 480 * 2 * 2);
+
+    // 標準出力を使うものとは併用できない
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--raw", "s16", "--dump", "char", "CQ"])
+        .assert()
+        .failure();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--raw", "s16", "--output", "out.wav", "CQ"])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+fn output_compressed_file() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR"));
+
+    // (拡張子, ファイル先頭のマジックナンバー)
+    for (ext, magic) in [("flac", "fLaC"), ("ogg", "OggS")] {
+        let path = dir.join(format!("output_compressed_file.{}", ext));
+        let _ = std::fs::remove_file(&path);
+
+        let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+        cmd.args(&[
+            "--wpm",
+            "30",
+            "--frequency",
+            "700",
+            "--sample-rate",
+            "16000",
+        ])
+        .args(&["--output", path.to_str().unwrap()])
+        .args(&["--input", "tests/data/cq.txt"])
+        .assert()
+        .success();
+
+        let data = std::fs::read(&path)?;
+        assert!(data.starts_with(magic.as_bytes()), "{}", ext);
+
+        // タグは既定のプレイヤーと入力ファイル名から
+        let text = String::from_utf8_lossy(&data);
+        assert!(text.contains("TITLE=cq"), "{}", ext);
+        assert!(text.contains("WPM=30"), "{}", ext);
+        assert!(text.contains("FREQUENCY=700"), "{}", ext);
+    }
+
+    // 題名の指定
+    let path = dir.join("output_compressed_file_title.flac");
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["--title", "Lesson 1", "--sample-rate", "8000"])
+        .args(&["--output", path.to_str().unwrap()])
+        .arg("CQ")
+        .assert()
+        .success();
+    let data = std::fs::read(&path)?;
+    assert!(String::from_utf8_lossy(&data).contains("TITLE=Lesson 1"));
+
+    // 題名は出力ファイルがある場合のみ
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--title", "Lesson 1", "CQ"])
+        .assert()
+        .failure();
+
+    // Ogg Opus には未対応
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--output", "out.opus", "CQ"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Opus"));
+
+    Ok(())
+}
+
+#[test]
+fn output_device_selection() -> Result<(), Box<dyn std::error::Error>> {
+    // 一覧表示は単独で指定する(一覧表示そのものは音声デバイスのない環境では試せない)
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["--list-devices", "CQ"]).assert().failure();
+
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["--list-devices", "--wpm", "20"])
+        .assert()
+        .failure();
+
+    // 存在しないホストはパニックせずにエラー
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.args(&["--host", "no-such-host", "CQ"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "host(no-such-host) is not available",
+        ));
+
+    // ファイルへの書き出しとは併用できない
+    let mut cmd = Command::cargo_bin(env!("CARGO_PKG_NAME"))?;
+    cmd.arg("--debug")
+        .args(&["--device", "USB Audio", "--output", "out.wav", "CQ"])
+        .assert()
+        .failure();
+
+    Ok(())
+}